@@ -1,4 +1,4 @@
-use std::any::TypeId;
+use std::{any::TypeId, marker::PhantomData};
 
 use bevy::{
     asset::{ReflectAsset, UntypedAssetId},
@@ -18,9 +18,13 @@ use bevy_render::camera::Viewport;
 use bevy_window::PrimaryWindow;
 use egui::{FontId, RichText, Widget};
 use egui_dock::{DockArea, DockState, NodeIndex, Style};
-use leaves_bm::Bound3;
+use leaves_bm::{
+    lbm::{BounceBack, Collision, MrtRates, VelocitySet},
+    math::Vec3,
+    Float,
+};
 
-use crate::{SimulationRes, X_COUNT, Y_COUNT, Z_COUNT};
+use crate::SimulationRes;
 
 #[derive(Resource)]
 pub struct SimControls {
@@ -51,10 +55,14 @@ pub struct ColorBounds {
 
 #[derive(Resource, Clone)]
 pub struct Constants {
-    pub time_relaxation_constant: f32,
-    pub speed_of_sound: f32,
-    pub particle_mass: f32,
-    pub particle_velocity_decay: f32,
+    pub time_relaxation_constant: Float,
+    pub speed_of_sound: Float,
+    pub particle_mass: Float,
+    pub particle_velocity_decay: Float,
+    pub bounce_back: BounceBack,
+    pub collision: Collision,
+    pub smagorinsky: Option<Float>,
+    pub gravity: Vec3,
 }
 
 impl From<leaves_bm::lbm::Constants> for Constants {
@@ -64,6 +72,10 @@ impl From<leaves_bm::lbm::Constants> for Constants {
             speed_of_sound,
             particle_mass,
             particle_velocity_decay,
+            bounce_back,
+            collision,
+            smagorinsky,
+            gravity,
         }: leaves_bm::lbm::Constants,
     ) -> Self {
         Self {
@@ -71,6 +83,10 @@ impl From<leaves_bm::lbm::Constants> for Constants {
             speed_of_sound,
             particle_mass,
             particle_velocity_decay,
+            bounce_back,
+            collision,
+            smagorinsky,
+            gravity,
         }
     }
 }
@@ -81,6 +97,10 @@ impl From<Constants> for leaves_bm::lbm::Constants {
             speed_of_sound,
             particle_mass,
             particle_velocity_decay,
+            bounce_back,
+            collision,
+            smagorinsky,
+            gravity,
         }: Constants,
     ) -> Self {
         Self {
@@ -88,6 +108,10 @@ impl From<Constants> for leaves_bm::lbm::Constants {
             speed_of_sound,
             particle_mass,
             particle_velocity_decay,
+            bounce_back,
+            collision,
+            smagorinsky,
+            gravity,
         }
     }
 }
@@ -99,7 +123,7 @@ pub struct Probe {
     pub z: usize,
 }
 
-pub fn show_ui_system(world: &mut World) {
+pub fn show_ui_system<V: VelocitySet<Q>, const Q: usize>(world: &mut World) {
     let Ok(egui_context) = world
         .query_filtered::<&mut EguiContext, With<PrimaryEguiContext>>()
         .single(world)
@@ -109,7 +133,7 @@ pub fn show_ui_system(world: &mut World) {
     let mut egui_context = egui_context.clone();
 
     world.resource_scope::<UiState, _>(|world, mut ui_state| {
-        ui_state.ui(world, egui_context.get_mut())
+        ui_state.ui::<V, Q>(world, egui_context.get_mut())
     });
 }
 
@@ -177,12 +201,17 @@ impl UiState {
         }
     }
 
-    fn ui(&mut self, world: &mut World, ctx: &mut egui::Context) {
-        let mut tab_viewer = TabViewer {
+    fn ui<V: VelocitySet<Q>, const Q: usize>(
+        &mut self,
+        world: &mut World,
+        ctx: &mut egui::Context,
+    ) {
+        let mut tab_viewer = TabViewer::<V, Q> {
             world,
             viewport_rect: &mut self.viewport_rect,
             selected_entities: &mut self.selected_entities,
             selection: &mut self.selection,
+            velocity_set: PhantomData,
         };
         DockArea::new(&mut self.state)
             .style(Style::from_egui(ctx.style().as_ref()))
@@ -199,14 +228,16 @@ enum EguiWindow {
     Inspector,
 }
 
-struct TabViewer<'a> {
+struct TabViewer<'a, V, const Q: usize> {
     world: &'a mut World,
     selected_entities: &'a mut SelectedEntities,
     selection: &'a mut InspectorSelection,
     viewport_rect: &'a mut egui::Rect,
+    /// The lattice of the [`SimulationRes`] to show.
+    velocity_set: PhantomData<V>,
 }
 
-impl egui_dock::TabViewer for TabViewer<'_> {
+impl<V: VelocitySet<Q>, const Q: usize> egui_dock::TabViewer for TabViewer<'_, V, Q> {
     type Tab = EguiWindow;
 
     fn ui(&mut self, ui: &mut egui_dock::egui::Ui, window: &mut Self::Tab) {
@@ -272,6 +303,61 @@ impl egui_dock::TabViewer for TabViewer<'_> {
                             .text("Velocity Decay")
                             .logarithmic(true),
                     );
+                    let bounce_back = &mut constants.bounce_back;
+                    ui.label("Bounce Back");
+                    ui.radio_value(bounce_back, BounceBack::Halfway, "Halfway");
+                    ui.radio_value(bounce_back, BounceBack::Interpolated, "Interpolated");
+                    let collision = &mut constants.collision;
+                    ui.label("Collision");
+                    ui.radio_value(collision, Collision::Bgk, "BGK");
+                    // The moment bases only exist on D3Q19.
+                    let mrt = Collision::Mrt(MrtRates::default());
+                    if mrt.supports(Q)
+                        && ui
+                            .radio(matches!(collision, Collision::Mrt(_)), "MRT")
+                            .clicked()
+                    {
+                        *collision = mrt;
+                    }
+                    if ui
+                        .radio(matches!(collision, Collision::Trt { .. }), "TRT")
+                        .clicked()
+                    {
+                        *collision = Collision::Trt { magic: 3.0 / 16.0 };
+                    }
+                    ui.radio_value(collision, Collision::Regularized, "Regularized");
+                    ui.radio_value(collision, Collision::Entropic, "Entropic");
+                    let central = Collision::CentralMoment {
+                        bulk: 1.0,
+                        higher: 1.0,
+                    };
+                    if central.supports(Q)
+                        && ui
+                            .radio(
+                                matches!(collision, Collision::CentralMoment { .. }),
+                                "Central Moment",
+                            )
+                            .clicked()
+                    {
+                        *collision = central;
+                    }
+                    if let Collision::Trt { magic } = collision {
+                        ui.add(
+                            egui::Slider::new(magic, 0.01..=1.0)
+                                .text("Magic")
+                                .logarithmic(true),
+                        );
+                    }
+                    let mut les = constants.smagorinsky.is_some();
+                    if ui.checkbox(&mut les, "Smagorinsky").changed() {
+                        constants.smagorinsky = les.then_some(0.17);
+                    }
+                    if let Some(smagorinsky) = &mut constants.smagorinsky {
+                        ui.add(egui::Slider::new(smagorinsky, 0.05..=0.3).text("Smagorinsky"));
+                    }
+                    ui.add(
+                        egui::Slider::new(&mut constants.gravity.y, -0.01..=0.01).text("Gravity"),
+                    );
                 }
                 if let Some(mut bounds) = self.world.get_resource_mut::<ColorBounds>() {
                     let min = bounds.min;
@@ -288,12 +374,16 @@ impl egui_dock::TabViewer for TabViewer<'_> {
                 }
 
                 let probe = self.world.get_resource::<Probe>();
-                let values = self.world.get_resource().map(|s: &SimulationRes| {
-                    if let Some(probe) = probe {
+                let size = self
+                    .world
+                    .get_resource::<SimulationRes<V, Q>>()
+                    .map(|s| s.0.size);
+                let values = self.world.get_resource().map(|s: &SimulationRes<V, Q>| {
+                    if let Some(loc) = probe.and_then(|p| s.0.size.bound(p.x, p.y, p.z).ok()) {
                         s.0.distributions
                             .iter()
                             .map(|(d, dir, _)| {
-                                let v = d.get(Bound3::new(probe.x, probe.y, probe.z).unwrap());
+                                let v = d.get(loc);
                                 format!("[{:+} {:+} {:+}] = {:.4}", dir.x, dir.y, dir.z, v)
                             })
                             .collect::<Vec<_>>()
@@ -301,19 +391,44 @@ impl egui_dock::TabViewer for TabViewer<'_> {
                         vec![]
                     }
                 });
-                self.world.get_resource::<SimulationRes>().map(|s| {
+                self.world.get_resource::<SimulationRes<V, Q>>().map(|s| {
                     ui.label(format!(
                         "Step: {}",
                         s.0.sim_step
                             .as_ref()
                             .map(|s| s.to_string())
                             .unwrap_or_default()
-                    ))
+                    ));
+                    let stability = s.0.stability();
+                    egui::Label::new(
+                        RichText::new(format!(
+                            "{}\n{stability}",
+                            if stability.is_stable() {
+                                "Stable"
+                            } else {
+                                "Unstable"
+                            }
+                        ))
+                        .font(FontId::monospace(12.0)),
+                    )
+                    .ui(ui);
+                    for (i, f) in s.0.mesh_forces.iter().enumerate() {
+                        egui::Label::new(
+                            RichText::new(format!(
+                                "Mesh {i}\n  force  {}\n  torque {}",
+                                f.force, f.torque
+                            ))
+                            .font(FontId::monospace(12.0)),
+                        )
+                        .ui(ui);
+                    }
                 });
-                if let Some(mut probe) = self.world.get_resource_mut::<Probe>() {
-                    ui.add(egui::Slider::new(&mut probe.x, 0..=(X_COUNT - 1)).text("X probe"));
-                    ui.add(egui::Slider::new(&mut probe.y, 0..=(Y_COUNT - 1)).text("Y probe"));
-                    ui.add(egui::Slider::new(&mut probe.z, 0..=(Z_COUNT - 1)).text("Z probe"));
+                if let (Some(mut probe), Some(size)) =
+                    (self.world.get_resource_mut::<Probe>(), size)
+                {
+                    ui.add(egui::Slider::new(&mut probe.x, 0..=(size.x - 1)).text("X probe"));
+                    ui.add(egui::Slider::new(&mut probe.y, 0..=(size.y - 1)).text("Y probe"));
+                    ui.add(egui::Slider::new(&mut probe.z, 0..=(size.z - 1)).text("Z probe"));
                     if let Some(values) = values {
                         ui.label("values");
 
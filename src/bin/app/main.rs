@@ -7,8 +7,8 @@ use bevy::{prelude::*, render::view::NoFrustumCulling};
 use bevy_egui::PrimaryEguiContext;
 use bevy_render::view::RenderLayers;
 use leaves_bm::{
-    lbm::{Constants, Initializer, Simulation},
-    Bound3,
+    lbm::{BounceBack, Collision, Constants, Initializer, Simulation, VelocitySet, D2Q9, D3Q19},
+    Float, Size3,
 };
 use rand::{rngs::SmallRng, SeedableRng};
 
@@ -19,18 +19,20 @@ use crate::{
     render::{CustomMaterialPlugin, InstanceData, InstanceMaterialData},
 };
 
-const X_COUNT: usize = 25;
-const Y_COUNT: usize = 25;
-const Z_COUNT: usize = 25;
+/// The grid size when none is given on the command line.
+const DEFAULT_SIZE: Size3 = Size3::new(25, 25, 25);
 const PARTICLE_COUNT: usize = 50;
 const RNG_SEED: u64 = 0xDEADBEEF;
 
 /// initialize 3d scene objects
-fn setup(
+fn setup<V: VelocitySet<Q>, const Q: usize>(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    sim: Res<SimulationRes<V, Q>>,
 ) {
+    let size = sim.0.size;
+    let flat = size.z == 1;
     // circular base
     commands.spawn((
         Mesh3d(meshes.add(Rectangle::new(1.0, 2.0))),
@@ -54,21 +56,25 @@ fn setup(
     );
     commands.spawn(bundle);
 
-    // instanced boxes
+    // instanced boxes, or tiles of a heat map for a flat simulation
+    let point = match flat {
+        true => meshes.add(Rectangle::new(1.0, 1.0)),
+        false => meshes.add(Cuboid::new(0.3, 0.3, 0.3)),
+    };
     let bundle = (
-        Mesh3d(meshes.add(Cuboid::new(0.3, 0.3, 0.3))),
+        Mesh3d(point),
         render::GridPoint,
         InstanceMaterialData(
-            (0..X_COUNT)
-                .flat_map(|x| (0..Y_COUNT).map(move |y| (x, y)))
-                .flat_map(|(x, y)| (0..Z_COUNT).map(move |z| (x, y, z)))
+            (0..size.x)
+                .flat_map(|x| (0..size.y).map(move |y| (x, y)))
+                .flat_map(|(x, y)| (0..size.z).map(move |z| (x, y, z)))
                 .map(|(x, y, z)| {
                     let (x, y, z) = (x as f32, y as f32, z as f32);
                     InstanceData {
                         position: bevy::prelude::Vec3::new(
-                            X_COUNT as f32 / 2.0 - x,
-                            Y_COUNT as f32 / 2.0 - y,
-                            Z_COUNT as f32 / 2.0 - z,
+                            size.x as f32 / 2.0 - x,
+                            size.y as f32 / 2.0 - y,
+                            size.z as f32 / 2.0 - z,
                         ),
                         scale: 1.0,
                         color: LinearRgba::from(Color::WHITE).to_f32_array(),
@@ -93,17 +99,19 @@ fn setup(
     ));
 
     // pan orbit camera (in a panel)
-    spawn_camera(commands);
+    spawn_camera(commands, flat);
 }
 
 mod init {
     use leaves_bm::{
         lbm::{InitArgs, Particle},
         math::{Int3, Vec3},
+        mesh::{Mesh, Triangle},
+        Float, Size3,
     };
     use rand::Rng;
 
-    use crate::{PARTICLE_COUNT, X_COUNT, Y_COUNT, Z_COUNT};
+    use crate::PARTICLE_COUNT;
 
     pub fn moving_wave(
         InitArgs {
@@ -111,7 +119,7 @@ mod init {
             dir,
             ..
         }: InitArgs,
-    ) -> Option<f32> {
+    ) -> Option<Float> {
         let vec: Vec3 = dir.into();
         if x != 0 {
             return None;
@@ -124,14 +132,15 @@ mod init {
         InitArgs {
             loc: (x, _, _),
             dir,
+            size,
             ..
         }: InitArgs,
-    ) -> Option<f32> {
+    ) -> Option<Float> {
         let vec: Vec3 = dir.into();
-        if x != 0 && x + 1 != X_COUNT {
+        if x != 0 && x + 1 != size.x {
             return None;
         }
-        let x_f = x as f32 - (X_COUNT as f32 / 2.0);
+        let x_f = x as Float - (size.x as Float / 2.0);
         let magnitude = vec.dot(Vec3::new(if x_f > 0.0 { -1.0 } else { 1.0 }, 0.0, 0.0)) / 20.0;
         (magnitude > 0.0).then_some(magnitude)
     }
@@ -140,11 +149,12 @@ mod init {
             loc: (x, y, z),
             dir,
             weight,
+            size,
         }: InitArgs,
-    ) -> Option<f32> {
+    ) -> Option<Float> {
         let vec: Vec3 = dir.into();
-        let x_range = (X_COUNT / 4)..(X_COUNT * 3 / 4);
-        let y_range = (Y_COUNT / 4)..(Y_COUNT * 3 / 4);
+        let x_range = (size.x / 4)..(size.x * 3 / 4);
+        let y_range = (size.y / 4)..(size.y * 3 / 4);
         if !x_range.contains(&x) {
             return None;
         }
@@ -155,8 +165,8 @@ mod init {
             return None;
         }
         let (x_f, y_f) = (
-            (x as i32 - (X_COUNT as i32 / 2)) as f32,
-            (y as i32 - (Y_COUNT as i32 / 2)) as f32,
+            (x as i32 - (size.x as i32 / 2)) as Float,
+            (y as i32 - (size.y as i32 / 2)) as Float,
         );
         let magnitude = vec.dot(Vec3::new(-y_f, x_f, 0.0)) * weight;
         (magnitude > 0.0).then_some(magnitude)
@@ -165,10 +175,11 @@ mod init {
         InitArgs {
             loc: (x, y, z),
             dir,
+            size,
             ..
         }: InitArgs,
-    ) -> Option<f32> {
-        if x != X_COUNT / 2 || y != Y_COUNT / 2 || z != Z_COUNT / 2 {
+    ) -> Option<Float> {
+        if x != size.x / 2 || y != size.y / 2 || z != size.z / 2 {
             return None;
         }
         if dir == Int3::ZERO {
@@ -178,18 +189,40 @@ mod init {
         }
     }
 
-    pub fn particles<T: Rng>(rng: &mut T) -> Vec<Particle<X_COUNT, Y_COUNT, Z_COUNT>> {
+    /// A wall across the middle of the x axis.
+    pub fn plane(size: Size3) -> Mesh {
+        let (x, y, z) = (size.x as Float / 2.0, size.y as Float, size.z as Float);
+        if size.z == 1 {
+            return Mesh::polyline(&[[x, 0.0], [x, y]], false);
+        }
+        Mesh::new(vec![
+            Triangle::new(
+                Vec3::new(x, 0.0, 0.0),
+                Vec3::new(x, y, 0.0),
+                Vec3::new(x, y, z),
+            ),
+            Triangle::new(
+                Vec3::new(x, 0.0, 0.0),
+                Vec3::new(x, y, z),
+                Vec3::new(x, 0.0, z),
+            ),
+        ])
+    }
+
+    pub fn particles<T: Rng>(rng: &mut T, size: Size3) -> Vec<Particle> {
         (0..PARTICLE_COUNT)
-            .map(|_| Particle::from_rng_bounds(rng))
+            .map(|_| Particle::from_rng_bounds(rng, size))
             .collect()
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-fn step_simulation(
+// The casts from `Float` to bevy's `f32` are no-ops unless the `f64` feature is
+// enabled.
+#[allow(clippy::too_many_arguments, clippy::unnecessary_cast)]
+fn step_simulation<V: VelocitySet<Q> + Default, const Q: usize>(
     time: Res<Time>,
     mut timer: ResMut<SimulationTimer>,
-    mut sim: ResMut<SimulationRes>,
+    mut sim: ResMut<SimulationRes<V, Q>>,
     mut controls: ResMut<SimControls>,
     params: Res<InitParams>,
     grid: Single<(&mut InstanceMaterialData, &render::GridPoint)>,
@@ -209,14 +242,24 @@ fn step_simulation(
     };
     let mut rerender = false;
 
-    sim.0.constants = constants.into_inner().clone().into();
+    if let Err(error) = sim.0.set_constants(constants.into_inner().clone().into()) {
+        warn!("{error}");
+    }
 
     if controls.restart_requested {
         rerender = true;
         controls.restart_requested = false;
 
         let mut rng = SmallRng::seed_from_u64(RNG_SEED);
-        let mut new_sim = Simulation::new(sim.0.constants, init::particles(&mut rng), vec![]);
+        let size = sim.0.size;
+        let mut new_sim = Simulation::with_velocity_set(
+            V::default(),
+            size,
+            *sim.0.constants(),
+            init::particles(&mut rng, size),
+            vec![init::plane(size)],
+        )
+        .expect("the constants were checked when they were set");
         new_sim.initialize(init_func);
 
         *sim = SimulationRes(new_sim);
@@ -240,16 +283,26 @@ fn step_simulation(
     rerender |= color_bounds.is_changed();
 
     if rerender {
+        let size = sim.0.size;
+        let flat = size.z == 1;
         let (mut grid, _) = grid.into_inner();
         #[allow(clippy::modulo_one)]
         for (i, data) in &mut grid.0.iter_mut().enumerate() {
-            let z = i % Z_COUNT;
-            let y = (i / Z_COUNT) % Y_COUNT;
-            let x = i / Z_COUNT / Y_COUNT;
+            let z = i % size.z;
+            let y = (i / size.z) % size.y;
+            let x = i / size.z / size.y;
 
-            let value = (*sim.0.density.get(Bound3::new(x, y, z).unwrap()) - color_bounds.min)
+            let value = (*sim.0.density.get(size.bound(x, y, z).unwrap()) as f32
+                - color_bounds.min)
                 / (color_bounds.max - color_bounds.min);
             let value = value.min(1.0);
+            if flat {
+                data.color = match probe.x == x && probe.y == y {
+                    true => [1.0; 4],
+                    false => heat(value),
+                };
+                continue;
+            }
             data.color = [value, value, value, 1.0];
 
             if probe.x == x && probe.y == y && probe.z == z {
@@ -267,32 +320,70 @@ fn step_simulation(
         iter_mut
             .zip(sim.0.particles.iter())
             .for_each(|(data, particle)| {
-                data.position.x = X_COUNT as f32 / 2.0 - particle.position.x;
-                data.position.y = Y_COUNT as f32 / 2.0 - particle.position.y;
-                data.position.z = Z_COUNT as f32 / 2.0 - particle.position.z;
+                data.position.x = size.x as f32 / 2.0 - particle.position.x as f32;
+                data.position.y = size.y as f32 / 2.0 - particle.position.y as f32;
+                data.position.z = size.z as f32 / 2.0 - particle.position.z as f32;
             });
     }
 }
 
+/// The colour of `value` between 0 and 1 on a heat map running from blue
+/// through green to red.
+fn heat(value: f32) -> [f32; 4] {
+    let band = |center: f32| (1.5 - (4.0 * value - center).abs()).clamp(0.0, 1.0);
+    [band(3.0), band(2.0), band(1.0), 1.0]
+}
+
 #[derive(Resource)]
 struct SimulationTimer(Timer);
 
 #[derive(Resource)]
-struct SimulationRes(Simulation<X_COUNT, Y_COUNT, Z_COUNT>);
+struct SimulationRes<V = D3Q19, const Q: usize = 19>(Simulation<V, Q>);
+
+/// The grid size from the command line, given as `X Y Z`, or as `X Y` for a
+/// flat simulation.
+fn grid_size() -> Size3 {
+    let args: Vec<usize> = std::env::args()
+        .skip(1)
+        .map(|arg| arg.parse().expect("grid sizes should be positive integers"))
+        .collect();
+    match args[..] {
+        [] => DEFAULT_SIZE,
+        [x, y] if x > 0 && y > 0 => Size3::new(x, y, 1),
+        [x, y, z] if x > 0 && y > 0 && z > 0 => Size3::new(x, y, z),
+        _ => panic!("expected no arguments or a grid size as X Y Z or X Y"),
+    }
+}
 
 fn main() {
+    let size = grid_size();
+    // A grid one point deep only needs the directions in its plane.
+    match size.z {
+        1 => run::<D2Q9, 9>(size),
+        _ => run::<D3Q19, 19>(size),
+    }
+}
+
+fn run<V: VelocitySet<Q> + Default, const Q: usize>(size: Size3) {
     let mut rng = SmallRng::seed_from_u64(RNG_SEED);
-    let mut sim = Simulation::new(
+    let mut sim = Simulation::with_velocity_set(
+        V::default(),
+        size,
         Constants {
             // Should be greater than 1 for some reason.
             time_relaxation_constant: 1.25,
-            speed_of_sound: 1.0 / (3.0_f32).sqrt(),
+            speed_of_sound: 1.0 / Float::sqrt(3.0),
             particle_mass: 0.15,
             particle_velocity_decay: 0.2,
+            bounce_back: BounceBack::Halfway,
+            collision: Collision::Bgk,
+            smagorinsky: None,
+            gravity: leaves_bm::math::Vec3::ZERO,
         },
-        init::particles(&mut rng),
-        vec![],
-    );
+        init::particles(&mut rng, size),
+        vec![init::plane(size)],
+    )
+    .expect("BGK works on every lattice");
 
     sim.initialize(Box::new(init::circular));
 
@@ -329,24 +420,27 @@ fn main() {
             function: Function::MovingWave,
             scale: 1.0,
         })
-        .insert_resource(egui::Constants::from(sim.constants))
+        .insert_resource(egui::Constants::from(*sim.constants()))
         .insert_resource(SimulationTimer(Timer::new(
             Duration::from_millis(50),
             TimerMode::Repeating,
         )))
         .insert_resource(UiState::new())
         .insert_resource(SimulationRes(sim))
-        .add_systems(Startup, setup)
-        .add_systems(bevy_egui::EguiPrimaryContextPass, egui::show_ui_system)
+        .add_systems(Startup, setup::<V, Q>)
+        .add_systems(
+            bevy_egui::EguiPrimaryContextPass,
+            egui::show_ui_system::<V, Q>,
+        )
         .add_systems(
             PostUpdate,
-            egui::set_camera_viewport.after(egui::show_ui_system),
+            egui::set_camera_viewport.after(egui::show_ui_system::<V, Q>),
         )
         .add_systems(
             Update,
             (
                 pan_orbit_camera.run_if(any_with_component::<PanOrbitState>),
-                step_simulation,
+                step_simulation::<V, Q>,
                 handle_keystrokes,
             ),
         )
@@ -79,7 +79,7 @@ impl Default for PanOrbitSettings {
     }
 }
 
-pub fn spawn_camera(mut commands: Commands) {
+pub fn spawn_camera(mut commands: Commands, flat: bool) {
     let mut camera = PanOrbitCameraBundle::default();
     camera.settings.zoom_key = Some(KeyCode::ShiftLeft);
     camera.settings.orbit_key = Some(KeyCode::AltLeft);
@@ -91,6 +91,10 @@ pub fn spawn_camera(mut commands: Commands) {
     camera.state.radius = 50.0;
     camera.state.pitch = 225.0f32.to_radians();
     camera.state.yaw = 180.0f32.to_radians();
+    // A flat simulation is seen face on, with x to the right and y up.
+    if flat {
+        camera.state.pitch = 180.0f32.to_radians();
+    }
 
     commands.spawn((Camera3d::default(), camera));
 }
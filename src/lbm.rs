@@ -1,48 +1,105 @@
+mod collision;
+mod domain;
 mod iteration;
+mod scalar;
+mod simd;
+mod streaming;
+mod thermal;
+mod velocity_set;
+mod voxel;
 
-use std::fmt::Display;
+use std::{fmt::Display, marker::PhantomData};
 
 use rand::Rng;
 
 use crate::{
-    math::{lerp, Bound3, Float, Int3, Vec3},
-    mesh::{Mesh, Triangle},
+    math::{Bound3, Float, Int3, Size3, Vec3},
+    mesh::{Aabb, Mesh},
 };
 
-pub struct Simulation<const X: usize, const Y: usize, const Z: usize> {
-    pub distributions: Lattice<X, Y, Z>,
-    pub velocity: Box<Field<X, Y, Z, Vec3>>,
-    pub density: Box<Field<X, Y, Z, Float>>,
-    pub constants: Constants,
-    pub particles: Vec<Particle<X, Y, Z>>,
+use collision::{guo_forcing, smagorinsky, Collider};
+pub use collision::{Collision, MrtRates, UnsupportedCollision};
+pub use domain::{DomainBoundaries, FaceCondition};
+pub use scalar::{Scalar, ScalarCondition};
+use thermal::body_force;
+pub use thermal::Thermal;
+pub use velocity_set::{VelocitySet, D2Q9, D3Q15, D3Q19, D3Q27, D3Q7};
+pub use voxel::{CellFlag, CutLink, Voxels};
+
+/// A fluid on a lattice whose packets stream along the `Q` directions of the
+/// velocity set `V`.
+pub struct Simulation<V = D3Q19, const Q: usize = 19> {
+    /// The number of lattice points along each axis.
+    pub size: Size3,
+    pub distributions: Lattice<V, Q>,
+    pub velocity: Field<Vec3>,
+    pub density: Field<Float>,
+    /// The Smagorinsky eddy viscosity used in the last collision, which is
+    /// zero unless [`Constants::smagorinsky`] is set.
+    pub eddy_viscosity: Field<Float>,
+    /// The body force per unit volume on the fluid at each point, such as
+    /// gravity or a pressure gradient driving a channel.
+    pub force: Field<Vec3>,
+    /// Quantities such as smoke that the fluid carries along, which are
+    /// stepped with it.
+    pub scalars: Vec<Scalar>,
+    /// The temperature of the fluid, which adds buoyancy to `force` when
+    /// set.
+    pub thermal: Option<Thermal>,
+    /// Changed with [`Simulation::set_constants`], which checks that the
+    /// collision works on the lattice.
+    constants: Constants,
+    pub particles: Vec<Particle>,
     pub meshes: Vec<Mesh>,
-    pub sim_step: Option<SimStep<X, Y, Z>>,
+    /// The meshes rasterized onto the lattice, rebuilt when `None`.
+    ///
+    /// Reset this after changing the triangles of any mesh.
+    pub voxels: Option<Voxels>,
+    pub boundaries: DomainBoundaries,
+    /// The force and torque the fluid put on each mesh in the last step.
+    pub mesh_forces: Vec<MeshForce>,
+    pub sim_step: Option<SimStep>,
+    /// Whether `density` and `velocity` are from before the last collision,
+    /// as [`Simulation::step`] leaves them, rather than after the last stream.
+    stale_conditions: bool,
+}
+/// The push of the fluid on a mesh, in lattice units.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MeshForce {
+    pub force: Vec3,
+    /// Torque about the mesh's center.
+    pub torque: Vec3,
 }
+
 pub struct InitArgs {
     pub loc: (usize, usize, usize),
     pub dir: Int3,
     pub weight: Float,
+    /// The size of the simulation being initialized.
+    pub size: Size3,
 }
-impl From<(usize, usize, usize, Int3, Float)> for InitArgs {
-    fn from(value: (usize, usize, usize, Int3, Float)) -> Self {
+impl From<(usize, usize, usize, Int3, Float, Size3)> for InitArgs {
+    fn from(value: (usize, usize, usize, Int3, Float, Size3)) -> Self {
         Self {
             loc: (value.0, value.1, value.2),
             dir: value.3,
             weight: value.4,
+            size: value.5,
         }
     }
 }
-pub type Initializer = Box<dyn Fn(InitArgs) -> Option<f32>>;
+pub type Initializer = Box<dyn Fn(InitArgs) -> Option<Float>>;
 
-pub enum SimStep<const X: usize, const Y: usize, const Z: usize> {
+pub enum SimStep {
     Collide,
     BoundaryCondition,
     Stream,
     CalcMacro,
     StreamParticles,
+    MoveMeshes,
 }
 
-impl<const X: usize, const Y: usize, const Z: usize> Display for SimStep<X, Y, Z> {
+impl Display for SimStep {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
             SimStep::Collide => "Collide",
@@ -50,32 +107,96 @@ impl<const X: usize, const Y: usize, const Z: usize> Display for SimStep<X, Y, Z
             SimStep::Stream => "Stream",
             SimStep::CalcMacro => "CalcMacro",
             SimStep::StreamParticles => "StreamParticles",
+            SimStep::MoveMeshes => "MoveMeshes",
         })
     }
 }
 
-impl<const X: usize, const Y: usize, const Z: usize> Simulation<X, Y, Z> {
-    pub fn new(constants: Constants, particles: Vec<Particle<X, Y, Z>>, meshes: Vec<Mesh>) -> Self {
-        Self {
-            distributions: Lattice::default(),
-            velocity: Box::new(Field::default()),
-            density: Box::new(Field::new_from(1.0)),
+impl Simulation {
+    /// A simulation on the [`D3Q19`] lattice.
+    pub fn new(
+        size: Size3,
+        constants: Constants,
+        particles: Vec<Particle>,
+        meshes: Vec<Mesh>,
+    ) -> Self {
+        Self::with_velocity_set(D3Q19, size, constants, particles, meshes)
+            .expect("every collision works on D3Q19")
+    }
+}
+
+/// A flat simulation in the xy plane on the [`D2Q9`] lattice.
+pub type Simulation2d = Simulation<D2Q9, 9>;
+
+impl Simulation2d {
+    /// A flat simulation of `width` by `height` points, one point deep in z.
+    ///
+    /// Walls for it are built with [`Mesh::polyline`], and the z faces of its
+    /// `boundaries` must stay periodic.
+    pub fn new_2d(
+        width: usize,
+        height: usize,
+        constants: Constants,
+        particles: Vec<Particle>,
+        meshes: Vec<Mesh>,
+    ) -> Result<Self, UnsupportedCollision> {
+        let size = Size3::new(width, height, 1);
+        Self::with_velocity_set(D2Q9, size, constants, particles, meshes)
+    }
+}
+
+impl<V: VelocitySet<Q>, const Q: usize> Simulation<V, Q> {
+    /// A simulation on the lattice of `velocity_set`, such as [`D3Q27`] or
+    /// [`D2Q9`], unless the collision of `constants` does not work on it.
+    pub fn with_velocity_set(
+        _velocity_set: V,
+        size: Size3,
+        constants: Constants,
+        particles: Vec<Particle>,
+        meshes: Vec<Mesh>,
+    ) -> Result<Self, UnsupportedCollision> {
+        check_collision::<Q>(&constants)?;
+        Ok(Self {
+            size,
+            distributions: Lattice::new(size),
+            velocity: Field::new(size),
+            density: Field::new_from(size, 1.0),
+            eddy_viscosity: Field::new(size),
+            force: Field::new(size),
+            scalars: vec![],
+            thermal: None,
             constants,
             particles,
             meshes,
+            voxels: None,
+            boundaries: DomainBoundaries::default(),
+            mesh_forces: vec![],
             sim_step: None,
-        }
+            stale_conditions: false,
+        })
+    }
+
+    pub fn constants(&self) -> &Constants {
+        &self.constants
+    }
+
+    /// Replace the constants, unless the new collision does not work on this
+    /// lattice.
+    pub fn set_constants(&mut self, constants: Constants) -> Result<(), UnsupportedCollision> {
+        check_collision::<Q>(&constants)?;
+        self.constants = constants;
+        Ok(())
     }
 
     pub fn initialize(&mut self, value: Initializer) {
         self.distributions
             .iter_mut()
             .for_each(|(dist, dir, weight)| {
-                for x in 0..X {
-                    for y in 0..Y {
-                        for z in 0..Z {
-                            *dist.get_mut(Bound3::new(x, y, z).unwrap()) =
-                                value((x, y, z, dir, weight).into())
+                for x in 0..self.size.x {
+                    for y in 0..self.size.y {
+                        for z in 0..self.size.z {
+                            *dist.get_mut(self.size.bound(x, y, z).unwrap()) =
+                                value((x, y, z, dir, weight, self.size).into())
                                     .unwrap_or(if dir == Int3::ZERO { 1.0 } else { 0.0 });
                         }
                     }
@@ -86,21 +207,41 @@ impl<const X: usize, const Y: usize, const Z: usize> Simulation<X, Y, Z> {
 
     // https://en.wikipedia.org/wiki/Lattice_Boltzmann_methods#Example_implementation
     // but in 3D
+    /// Advance one time step, colliding and streaming in a single pass over
+    /// the lattice.
+    ///
+    /// This leaves `density` and `velocity` as the collision used them, one
+    /// stream behind the packets. [`Simulation::small_step`] takes the same
+    /// step one stage at a time, which is slower but shows every stage.
     pub fn step(&mut self) {
-        // let mut collided_packets = self.collide();
-        // // TODO: calculate for boundary positions
-        // self.update_boundary(&mut collided_packets);
-        // self.stream(&collided_packets);
-        // self.calc_conditions();
-        // self.stream_particles();
-        while !matches!(self.small_step(), SimStep::Collide) {}
+        // Finish a step that was started with `small_step` instead.
+        if !matches!(self.sim_step, None | Some(SimStep::Collide)) {
+            while !matches!(self.small_step(), SimStep::Collide) {}
+            return;
+        }
+        self.apply_buoyancy();
+        self.collide_and_stream();
+        // Meshes bounce back packets from before streaming, so the stream is
+        // undone around them.
+        self.distributions.shift(-1);
+        self.update_boundary();
+        self.distributions.shift(1);
+        self.apply_domain_boundaries();
+        self.step_scalars();
+        self.stream_particles();
+        self.move_meshes();
     }
 
-    pub fn small_step(&mut self) -> &SimStep<X, Y, Z> {
+    pub fn small_step(&mut self) -> &SimStep {
         let sim_step = self.sim_step.take().unwrap_or(SimStep::Collide);
         match sim_step {
             SimStep::Collide => {
+                self.apply_buoyancy();
+                if self.stale_conditions {
+                    self.calc_conditions();
+                }
                 self.collide();
+                self.step_scalars();
                 self.sim_step = Some(SimStep::BoundaryCondition);
             }
             SimStep::BoundaryCondition => {
@@ -117,6 +258,10 @@ impl<const X: usize, const Y: usize, const Z: usize> Simulation<X, Y, Z> {
             }
             SimStep::StreamParticles => {
                 self.stream_particles();
+                self.sim_step = Some(SimStep::MoveMeshes)
+            }
+            SimStep::MoveMeshes => {
+                self.move_meshes();
                 self.sim_step = Some(SimStep::Collide)
             }
         }
@@ -124,164 +269,193 @@ impl<const X: usize, const Y: usize, const Z: usize> Simulation<X, Y, Z> {
     }
 
     fn collide(&mut self) {
-        let mut new_packets = Box::new(Lattice::default());
-        for ((distribution, direction, weight), (new_dist, _, _)) in
-            self.distributions.iter().zip(new_packets.iter_mut())
-        {
-            for x in 0..X {
-                for y in 0..Y {
-                    for z in 0..Z {
-                        let loc = (x, y, z).try_into().unwrap();
-                        let flow_velocity = self.velocity.get(loc);
-                        let direction_magnitude = flow_velocity.dot(direction.into());
-                        let dm = direction_magnitude;
-                        let c = self.constants.speed_of_sound;
-                        let c2 = c * c;
-                        // Taylor expansion of equilibrium term in this direction.
-                        let equilibrium = weight
-                            * self.density.get(loc)
-                            * (1.0 + dm / c2 + dm * dm / (2.0 * c2 * c2)
-                                - flow_velocity.dot(*flow_velocity) / (2.0 * c2));
-
-                        // Wikipedia uses
-                        // lerp(current, equilibrium, (TRC-1)/TRC)
-                        // where TRC=time_relaxation_constant
-                        *new_dist.get_mut(loc) = lerp(
-                            *distribution.get(loc),
-                            equilibrium,
-                            self.constants.time_relaxation_constant,
-                        );
-                    }
+        let relaxation = Relaxation::new::<V>(&self.constants);
+        let size = self.size;
+        let (density, velocity) = (&self.density, &self.velocity);
+        let force = body_force(&self.force, &self.thermal);
+        let planes: Vec<_> = self
+            .distributions
+            .planes()
+            .into_iter()
+            .zip(self.eddy_viscosity.planes_mut())
+            .enumerate()
+            .collect();
+        map_in_parallel(planes, |(x, (mut plane, eddy_viscosities))| {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let loc = size.bound(x, y, z).unwrap();
+                    let (relaxed, eddy_viscosity) = relaxation.relax(
+                        plane.cell(y, z),
+                        *density.get(loc),
+                        *velocity.get(loc),
+                        *force.get(loc),
+                    );
+                    eddy_viscosities[y * size.z + z] = eddy_viscosity;
+                    plane.set_cell(y, z, relaxed);
                 }
             }
-        }
-        self.distributions = *new_packets;
+        });
     }
 
     fn update_boundary(&mut self) {
-        let bounds = (X, Y, Z);
-        // For each point and each packet distribution
-        // - if that direction from that point crosses a mesh plane
-        //   - calculate the proportion of the link before the plane
-        //   - calculate the mesh speed at/around that point
-        //   - go to the target cell and calculate the backwards packet dist
-        //   - overwrite the target cell packet dist (not add)
-        //
-        //
-        let triangle = Triangle::new(
-            Vec3::new(X as Float / 2.0, 0.0, 0.0),
-            Vec3::new(X as Float / 2.0, Y as Float, 0.0),
-            Vec3::new(X as Float / 2.0, Y as Float, Z as Float),
-        );
-        let triangle2 = Triangle::new(
-            Vec3::new(X as Float / 2.0, 0.0, 0.0),
-            Vec3::new(X as Float / 2.0, Y as Float, Z as Float),
-            Vec3::new(X as Float / 2.0, 0.0, Z as Float),
-        );
-        for x in 0..bounds.0 {
-            for y in 0..bounds.1 {
-                for z in 0..bounds.2 {
-                    for [(dist1, dir1, _), (dist2, dir2, _)] in self.distributions.iter_pairs() {
-                        let p0 = Vec3::new(x as Float, y as Float, z as Float);
-                        let p1 = p0 + dir1.into();
-                        if let Some(proportion) = triangle.intersect_proportion(p0, p1) {
-                            let loc = Int3::new(x as i32, y as i32, z as i32);
-                            let s = loc.wrap();
-                            let d = (loc + dir1).wrap();
-                            std::mem::swap(dist1.get_mut(s), dist2.get_mut(d));
-                            continue;
-                        }
-                        if let Some(proportion) = triangle2.intersect_proportion(p0, p1) {
-                            let loc = Int3::new(x as i32, y as i32, z as i32);
-                            let s = loc.wrap();
-                            let d = (loc + dir1).wrap();
-                            std::mem::swap(dist1.get_mut(s), dist2.get_mut(d));
+        // For each link that crosses a mesh
+        // - calculate the mesh speed at/around the crossing
+        // - go to the target cell and calculate the backwards packet dist
+        // - overwrite the target cell packet dist (not add)
+        // - add the momentum exchanged with the wall to the mesh's force
+        self.mesh_forces.clear();
+        self.mesh_forces
+            .resize(self.meshes.len(), MeshForce::default());
+        if self.meshes.is_empty() {
+            return;
+        }
+        let voxels = self
+            .voxels
+            .get_or_insert_with(|| Voxels::new::<V, Q>(self.size, &self.meshes));
+        let size = self.size;
+        let (bounce_back, boundaries) = (self.constants.bounce_back, self.boundaries);
+        let c = self.constants.speed_of_sound;
+        let c2 = c * c;
+        let (density, meshes) = (&self.density, &self.meshes);
+        // Interpolating reads a packet one more cell away from the wall, which
+        // has to be fluid on the same side of the domain.
+        let upstream = |loc: Int3, dist: &PacketDistribution| {
+            boundaries
+                .wrap(loc, size)
+                .filter(|cell| !voxels.is_solid(*cell))
+                .map(|cell| *dist.get(cell))
+        };
+        // Packets streaming into solid cells are dropped by resetting the
+        // cells to rest every step. This comes first, as the packets
+        // reflected back out of a solid cell are written into it.
+        map_in_parallel(
+            self.distributions.iter_mut().collect(),
+            |(dist, _, weight)| {
+                for x in 0..size.x {
+                    for y in 0..size.y {
+                        for z in 0..size.z {
+                            let loc = size.bound(x, y, z).unwrap();
+                            if voxels.is_solid(loc) {
+                                *dist.get_mut(loc) = weight;
+                            }
                         }
-                        // if x == 5 && dir1.x == 1 {
-                        //     let loc = Int3::new(x as i32, y as i32, z as i32);
-                        //     // if let Some(proportion) = triangle.intersect_proportion(p0, p1) {
-                        //     // Swap the values in the two distributions (assumes
-                        //     // the opposite direction will also intersect).
-                        //     let s = loc.wrap();
-                        //     let d = (loc + dir1).wrap();
-                        //     std::mem::swap(dist1.get_mut(s), dist2.get_mut(d));
-                        // }
-                        // if x == 5 && dir2.x == 1 {
-                        //     let loc = Int3::new(x as i32, y as i32, z as i32);
-                        //     let s = loc.wrap();
-                        //     let d = (loc + dir2).wrap();
-                        //     std::mem::swap(dist1.get_mut(d), dist2.get_mut(s));
-                        // }
-
-                        // if y == 5 && dir1.y == 1 {
-                        //     let loc = Int3::new(x as i32, y as i32, z as i32);
-                        //     // if let Some(proportion) = triangle.intersect_proportion(p0, p1) {
-                        //     // Swap the values in the two distributions (assumes
-                        //     // the opposite direction will also intersect).
-                        //     let s = loc.wrap();
-                        //     let d = (loc + dir1).wrap();
-                        //     std::mem::swap(dist1.get_mut(s), dist2.get_mut(d));
-                        // }
-                        // if y == 5 && dir2.y == 1 {
-                        //     let loc = Int3::new(x as i32, y as i32, z as i32);
-                        //     let s = loc.wrap();
-                        //     let d = (loc + dir2).wrap();
-                        //     std::mem::swap(dist1.get_mut(d), dist2.get_mut(s));
-                        // }
                     }
                 }
+            },
+        );
+        let pairs: Vec<_> = self
+            .distributions
+            .iter_pairs()
+            .into_iter()
+            .zip(&voxels.cut_links)
+            .collect();
+        // Each pair of directions only touches its own packets, so the pairs
+        // are bounced back independently and their forces added in order.
+        let pair_forces =
+            map_in_parallel(pairs, |([(dist1, dir1, weight), (dist2, _, _)], links)| {
+                let mut mesh_forces = vec![MeshForce::default(); meshes.len()];
+                // Every update for a pair of directions is computed from the
+                // collided packets before any of them are overwritten.
+                let mut updates = vec![];
+                for link in links {
+                    let loc = Int3::from(link.start);
+                    let s = link.start;
+                    let d = (loc + dir1).wrap(size);
+                    // A moving wall adds momentum to the packets it reflects.
+                    let crossing = Vec3::from(loc) + link.proportion * Vec3::from(dir1);
+                    let wall_velocity = meshes[link.mesh].velocity_at(crossing);
+                    let wall_momentum = 2.0 * weight * Vec3::from(dir1).dot(wall_velocity) / c2;
+                    // The packet stored in dist2 at d streams to s (and dist1 at s
+                    // streams to d), so the reflected packets are written to the
+                    // opposite end of the link. Nothing is reflected into solids.
+                    let to_s = (!voxels.is_solid(s)).then(|| {
+                        reflect(
+                            bounce_back,
+                            link.proportion,
+                            (*dist1.get(s), upstream(loc - dir1, dist1), *dist2.get(s)),
+                            density.get(s) * wall_momentum,
+                        )
+                    });
+                    let to_d = (!voxels.is_solid(d)).then(|| {
+                        reflect(
+                            bounce_back,
+                            1.0 - link.proportion,
+                            (
+                                *dist2.get(d),
+                                upstream(loc + dir1 + dir1, dist2),
+                                *dist1.get(d),
+                            ),
+                            -density.get(d) * wall_momentum,
+                        )
+                    });
+                    // The momentum taken from each reflected packet, measured
+                    // relative to the wall so that it does not depend on the frame
+                    // (Wen et al. 2014).
+                    let dir = Vec3::from(dir1);
+                    let mut force = Vec3::ZERO;
+                    if let Some(to_s) = to_s {
+                        force = force
+                            + *dist1.get(s) * (dir - wall_velocity)
+                            + to_s * (dir + wall_velocity);
+                    }
+                    if let Some(to_d) = to_d {
+                        force = force
+                            - *dist2.get(d) * (dir + wall_velocity)
+                            - to_d * (dir - wall_velocity);
+                    }
+                    let mesh_force = &mut mesh_forces[link.mesh];
+                    mesh_force.force = mesh_force.force + force;
+                    mesh_force.torque =
+                        mesh_force.torque + (crossing - meshes[link.mesh].center).cross(force);
+                    updates.push((s, to_d, d, to_s));
+                }
+                for &(s, to_d, d, to_s) in &updates {
+                    if let Some(to_d) = to_d {
+                        *dist1.get_mut(s) = to_d;
+                    }
+                    if let Some(to_s) = to_s {
+                        *dist2.get_mut(d) = to_s;
+                    }
+                }
+                mesh_forces
+            });
+        for mesh_forces in pair_forces {
+            for (total, pair) in self.mesh_forces.iter_mut().zip(mesh_forces) {
+                total.force = total.force + pair.force;
+                total.torque = total.torque + pair.torque;
             }
         }
     }
 
     fn stream(&mut self) {
-        let collided_packets = self.distributions.clone();
-        for ((new_dist, direction, _), (target, _, _)) in
-            collided_packets.iter().zip(self.distributions.iter_mut())
-        {
-            let bounds = (X as i32, Y as i32, Z as i32);
-            for x in 0..bounds.0 {
-                for y in 0..bounds.1 {
-                    for z in 0..(bounds.2) {
-                        let loc = Int3::new(x, y, z);
-                        *target.get_mut((loc + direction).wrap()) =
-                            *new_dist.get(loc.try_into().unwrap());
-                    }
-                }
-            }
-        }
+        self.distributions.shift(1);
+        self.apply_domain_boundaries();
     }
 
     pub fn calc_conditions(&mut self) {
-        // let mut total_mass = 0.0;
-        let mut total_momentum = Vec3::default();
-        for x in 0..X {
-            for y in 0..Y {
-                for z in 0..Z {
-                    let loc = (x, y, z).try_into().unwrap();
-                    let (packet_sum, direction_sum) = self
-                        .distributions
-                        .iter()
-                        .map(|(dist, dir, _)| {
-                            let packet = dist.get(loc);
-                            (*packet, *packet * Into::<Vec3>::into(dir))
-                        })
-                        .reduce(|acc, e| (acc.0 + e.0, acc.1 + e.1))
-                        .unwrap();
-                    let velocity = direction_sum / packet_sum;
-                    // total_mass += packet_sum;
-                    total_momentum = total_momentum + direction_sum;
-                    *self.density.get_mut(loc) = packet_sum;
-                    *self.velocity.get_mut(loc) = velocity;
+        let directions = V::DIRECTIONS;
+        let size = self.size;
+        let distributions = &self.distributions;
+        let force = body_force(&self.force, &self.thermal);
+        let planes: Vec<_> = self
+            .density
+            .planes_mut()
+            .zip(self.velocity.planes_mut())
+            .enumerate()
+            .collect();
+        map_in_parallel(planes, |(x, (densities, velocities))| {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let loc = size.bound(x, y, z).unwrap();
+                    let (packet_sum, direction_sum) =
+                        moments(&directions, &distributions.cell(loc));
+                    let point = y * size.z + z;
+                    densities[point] = packet_sum;
+                    velocities[point] = fluid_velocity(packet_sum, direction_sum, *force.get(loc));
                 }
             }
-        }
-        // println!(
-        //     "momentum = {total_momentum}, {}",
-        //     total_momentum.dot(total_momentum).sqrt()
-        // );
-        // dbg!(total_momentum);
-        // dbg!(total_mass, total_velocity);
+        });
+        self.stale_conditions = false;
     }
 
     fn stream_particles(&mut self) {
@@ -292,7 +466,7 @@ impl<const X: usize, const Y: usize, const Z: usize> Simulation<X, Y, Z> {
             particle.velocity = self.constants.particle_velocity_decay * particle.velocity
                 + flow_velocity / self.constants.particle_mass;
 
-            particle.position = (particle.position + particle.velocity).wrap((X, Y, Z));
+            particle.position = (particle.position + particle.velocity).wrap(self.size);
             // TODO: particles should be able to push back against flow.
             // magnitudes.push(velocity.dot(velocity).sqrt());
             // TODO: check magnitude of velocity.
@@ -300,76 +474,213 @@ impl<const X: usize, const Y: usize, const Z: usize> Simulation<X, Y, Z> {
         // dbg!(magnitudes.iter().max_by(|a, b| a.total_cmp(b)));
         // todo!("check velocity magnitude");
     }
+
+    /// How close the fluid is to blowing up, to compare collision operators.
+    ///
+    /// This is measured from the packets themselves, so it is current even
+    /// when `density` and `velocity` have not been recalculated since the last
+    /// [`Simulation::step`].
+    pub fn stability(&self) -> Stability {
+        let directions = V::DIRECTIONS;
+        let force = body_force(&self.force, &self.thermal);
+        let mut report = Stability {
+            max_mach: 0.0,
+            min_density: Float::INFINITY,
+            max_density: Float::NEG_INFINITY,
+            min_packet: Float::INFINITY,
+        };
+        let c = self.constants.speed_of_sound;
+        for x in 0..self.size.x {
+            for y in 0..self.size.y {
+                for z in 0..self.size.z {
+                    let loc = self.size.bound(x, y, z).unwrap();
+                    let packets = self.distributions.cell(loc);
+                    let (density, direction_sum) = moments(&directions, &packets);
+                    let velocity = fluid_velocity(density, direction_sum, *force.get(loc));
+                    let mach = velocity.dot(velocity).sqrt() / c;
+                    // NaN compares false, so it is carried through explicitly,
+                    // including past the cells after it.
+                    report.max_mach = if mach.is_nan() || report.max_mach.is_nan() {
+                        Float::NAN
+                    } else {
+                        report.max_mach.max(mach)
+                    };
+                    report.min_density = report.min_density.min(density);
+                    report.max_density = report.max_density.max(density);
+                    for packet in packets {
+                        report.min_packet = report.min_packet.min(packet);
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    fn move_meshes(&mut self) {
+        // The meshes that moved, with where they were and are now.
+        let mut moved = vec![];
+        for (i, (mesh, push)) in self.meshes.iter_mut().zip(&self.mesh_forces).enumerate() {
+            let weight = mesh
+                .rigid_body
+                .as_ref()
+                .map_or(Vec3::ZERO, |body| body.mass * self.constants.gravity);
+            let before = mesh.bounds();
+            if mesh.advance(push.force + weight, push.torque) {
+                if let Some(swept) = [before, mesh.bounds()]
+                    .into_iter()
+                    .flatten()
+                    .reduce(Aabb::union)
+                {
+                    moved.push((i, swept));
+                }
+            }
+        }
+        let Some(voxels) = &mut self.voxels else {
+            return;
+        };
+        let Some(swept) = moved.iter().map(|(_, swept)| *swept).reduce(Aabb::union) else {
+            return;
+        };
+        let uncovered = voxels.update::<V, Q>(&self.meshes, swept);
+        self.refill(&uncovered, &moved);
+    }
+
+    /// Fill cells that a mesh has moved off with fluid at equilibrium, as
+    /// dense as the fluid around them and moving with the mesh.
+    fn refill(&mut self, cells: &[Bound3], moved: &[(usize, Aabb)]) {
+        let Some(voxels) = &self.voxels else {
+            return;
+        };
+        let size = self.size;
+        let c = self.constants.speed_of_sound;
+        let densities: Vec<Float> = cells
+            .iter()
+            .map(|&loc| {
+                let neighbours: Vec<Float> = V::DIRECTIONS
+                    .iter()
+                    .filter_map(|&(direction, _)| {
+                        self.boundaries.wrap(Int3::from(loc) + direction, size)
+                    })
+                    .filter(|cell| !voxels.is_solid(*cell) && !cells.contains(cell))
+                    .map(|cell| *self.density.get(cell))
+                    .collect();
+                match neighbours.len() {
+                    0 => 1.0,
+                    n => neighbours.iter().sum::<Float>() / n as Float,
+                }
+            })
+            .collect();
+        for (&loc, density) in cells.iter().zip(densities) {
+            let point = Vec3::from(Int3::from(loc));
+            let velocity = moved
+                .iter()
+                .find(|(_, swept)| swept.contains(point))
+                .map_or(Vec3::ZERO, |&(i, _)| self.meshes[i].velocity_at(point));
+            let packets = V::DIRECTIONS
+                .map(|(direction, weight)| equilibrium(weight, density, velocity, direction, c));
+            self.distributions.set_cell(loc, packets);
+            *self.density.get_mut(loc) = density;
+            *self.velocity.get_mut(loc) = velocity;
+        }
+    }
 }
 
+/// The packets at every point, one distribution for each direction of the
+/// velocity set `V`.
 #[derive(Clone)]
-pub struct Lattice<const X: usize, const Y: usize, const Z: usize> {
-    pub q0: Box<PacketDistribution<X, Y, Z>>,
-    pub q1: Box<[PacketDistribution<X, Y, Z>; 6]>,
-    pub q2: Box<[PacketDistribution<X, Y, Z>; 12]>,
+pub struct Lattice<V = D3Q19, const Q: usize = 19> {
+    distributions: [PacketDistribution; Q],
+    velocity_set: PhantomData<V>,
 }
 
-impl<const X: usize, const Y: usize, const Z: usize> Default for Lattice<X, Y, Z> {
-    fn default() -> Self {
+impl<V: VelocitySet<Q>, const Q: usize> Lattice<V, Q> {
+    /// A lattice of `size` with every point at rest.
+    pub fn new(size: Size3) -> Self {
         Self {
-            q0: Box::new(PacketDistribution {
-                values: std::array::from_fn(|_| Box::new([[1.0; Z]; Y])),
+            distributions: V::DIRECTIONS.map(|(direction, _)| {
+                PacketDistribution::new(size, if direction == Int3::ZERO { 1.0 } else { 0.0 })
             }),
-            q1: Default::default(),
-            q2: Default::default(),
+            velocity_set: PhantomData,
         }
     }
+
+    pub fn size(&self) -> Size3 {
+        self.distributions[0].size
+    }
 }
 
 /// The packet distributions at each point in the lattice in a specific direction.
 #[derive(Clone)]
-pub struct PacketDistribution<const X: usize, const Y: usize, const Z: usize> {
-    values: [Box<[[Float; Z]; Y]>; X],
+pub struct PacketDistribution {
+    size: Size3,
+    /// How far the values are shifted from the points they belong to, which
+    /// lets packets stream without being moved.
+    offset: Int3,
+    /// One value per point, in the order of [`Size3::index`].
+    values: Vec<Float>,
 }
 
-impl<const X: usize, const Y: usize, const Z: usize> Default for PacketDistribution<X, Y, Z> {
-    fn default() -> Self {
+impl PacketDistribution {
+    pub fn new(size: Size3, value: Float) -> Self {
         Self {
-            values: std::array::from_fn(|_| Box::new([[0.0; Z]; Y])),
+            size,
+            offset: Int3::ZERO,
+            values: vec![value; size.count()],
         }
     }
-}
 
-impl<const X: usize, const Y: usize, const Z: usize> PacketDistribution<X, Y, Z> {
-    pub fn get(&self, bounds: Bound3<X, Y, Z>) -> &Float {
-        &self.values[bounds.x()][bounds.y()][bounds.z()]
+    pub fn get(&self, bounds: Bound3) -> &Float {
+        &self.values[self.index(bounds)]
     }
-    pub fn get_mut(&mut self, bounds: Bound3<X, Y, Z>) -> &mut Float {
-        &mut self.values[bounds.x()][bounds.y()][bounds.z()]
+    pub fn get_mut(&mut self, bounds: Bound3) -> &mut Float {
+        let index = self.index(bounds);
+        &mut self.values[index]
+    }
+
+    fn index(&self, bounds: Bound3) -> usize {
+        self.size
+            .index((Int3::from(bounds) + self.offset).wrap(self.size))
     }
 }
 
-pub struct Field<const X: usize, const Y: usize, const Z: usize, T> {
-    values: [[[T; Z]; Y]; X],
+pub struct Field<T> {
+    size: Size3,
+    values: Vec<T>,
 }
 
-impl<const X: usize, const Y: usize, const Z: usize, T: Default + Clone + Copy> Default
-    for Field<X, Y, Z, T>
-{
-    fn default() -> Self {
-        Self {
-            values: [[[T::default(); Z]; Y]; X],
-        }
+impl<T: Default + Clone + Copy> Field<T> {
+    pub fn new(size: Size3) -> Self {
+        Self::new_from(size, T::default())
     }
 }
 
-impl<const X: usize, const Y: usize, const Z: usize, T: Clone + Copy> Field<X, Y, Z, T> {
-    pub fn new_from(v: T) -> Self {
+impl<T: Clone + Copy> Field<T> {
+    pub fn new_from(size: Size3, v: T) -> Self {
         Self {
-            values: [[[v; Z]; Y]; X],
+            size,
+            values: vec![v; size.count()],
         }
     }
 
-    pub fn get(&self, bounds: Bound3<X, Y, Z>) -> &T {
-        &self.values[bounds.x()][bounds.y()][bounds.z()]
+    pub fn size(&self) -> Size3 {
+        self.size
+    }
+
+    /// The values for each x, in order.
+    fn planes(&self) -> std::slice::Chunks<'_, T> {
+        self.values.chunks(self.size.y * self.size.z)
+    }
+
+    /// The values for each x, in order.
+    fn planes_mut(&mut self) -> std::slice::ChunksMut<'_, T> {
+        self.values.chunks_mut(self.size.y * self.size.z)
+    }
+
+    pub fn get(&self, bounds: Bound3) -> &T {
+        &self.values[self.size.index(bounds)]
     }
-    pub fn get_mut(&mut self, bounds: Bound3<X, Y, Z>) -> &mut T {
-        &mut self.values[bounds.x()][bounds.y()][bounds.z()]
+    pub fn get_mut(&mut self, bounds: Bound3) -> &mut T {
+        &mut self.values[self.size.index(bounds)]
     }
 
     fn lerp_get(&self, location: Vec3) -> T
@@ -398,15 +709,16 @@ impl<const X: usize, const Y: usize, const Z: usize, T: Clone + Copy> Field<X, Y
             ]
             .into_iter()
         }
-        bounds(location.x, X)
-            .flat_map(|x| bounds(location.y, Y).map(move |y| (x, y)))
-            .flat_map(|(x, y)| bounds(location.z, Z).map(move |z| (x, y, z)))
+        let size = self.size;
+        bounds(location.x, size.x)
+            .flat_map(|x| bounds(location.y, size.y).map(move |y| (x, y)))
+            .flat_map(|(x, y)| bounds(location.z, size.z).map(move |z| (x, y, z)))
             .map(|(x, y, z)| {
                 let weight: Float = <Float as std::ops::Mul>::mul(
                     <Float as std::ops::Mul>::mul(x.weight, y.weight),
                     z.weight,
                 );
-                let coord = Bound3::new(x.coord, y.coord, z.coord).unwrap();
+                let coord = size.bound(x.coord, y.coord, z.coord).unwrap();
                 let v: T = weight * *self.get(coord);
                 v
             })
@@ -414,12 +726,199 @@ impl<const X: usize, const Y: usize, const Z: usize, T: Clone + Copy> Field<X, Y
     }
 }
 
+/// Map `f` over `items` in order, spread across threads.
+///
+/// Each item is mapped the same way on any number of threads, so results are
+/// identical to the serial path.
+#[cfg(feature = "parallel")]
+fn map_in_parallel<T: Send, R: Send>(items: Vec<T>, f: impl Fn(T) -> R + Send + Sync) -> Vec<R> {
+    use rayon::prelude::*;
+    items.into_par_iter().map(f).collect()
+}
+
+/// Map `f` over `items` in order. With the `parallel` feature this is spread
+/// across threads.
+#[cfg(not(feature = "parallel"))]
+fn map_in_parallel<T: Send, R: Send>(items: Vec<T>, f: impl Fn(T) -> R + Send + Sync) -> Vec<R> {
+    items.into_iter().map(f).collect()
+}
+
+/// The density and momentum of the packets at one point.
+fn moments<const Q: usize>(directions: &[(Int3, Float); Q], packets: &[Float; Q]) -> (Float, Vec3) {
+    packets.iter().zip(directions).fold(
+        (0.0, Vec3::ZERO),
+        |(density, momentum), (packet, (dir, _))| {
+            (density + packet, momentum + *packet * Vec3::from(*dir))
+        },
+    )
+}
+
+/// The velocity of the fluid at a point from its density and momentum.
+fn fluid_velocity(density: Float, momentum: Vec3, force: Vec3) -> Vec3 {
+    // Half of the body force acts before the packets are counted, as in Guo
+    // forcing.
+    (momentum + 0.5 * force) / density
+}
+
+/// Everything needed to collide the packets at a point.
+struct Relaxation<'a, const Q: usize> {
+    constants: &'a Constants,
+    directions: [(Int3, Float); Q],
+    collider: Collider<Q>,
+}
+
+impl<'a, const Q: usize> Relaxation<'a, Q> {
+    fn new<V: VelocitySet<Q>>(constants: &'a Constants) -> Self {
+        let directions = V::DIRECTIONS;
+        Self {
+            constants,
+            directions,
+            collider: Collider::new::<V>(constants.collision, constants.speed_of_sound),
+        }
+    }
+
+    /// Whether this is plain BGK, which has a kernel for several points at
+    /// once.
+    fn is_bgk(&self) -> bool {
+        matches!(self.collider, Collider::Bgk) && self.constants.smagorinsky.is_none()
+    }
+
+    /// Relax the packets at a point towards equilibrium and add the body
+    /// force, returning the new packets and the eddy viscosity used.
+    fn relax(
+        &self,
+        packets: [Float; Q],
+        density: Float,
+        velocity: Vec3,
+        force: Vec3,
+    ) -> ([Float; Q], Float) {
+        let directions = &self.directions;
+        let omega = self.constants.time_relaxation_constant;
+        let c = self.constants.speed_of_sound;
+        let equilibrium = directions
+            .map(|(direction, weight)| equilibrium(weight, density, velocity, direction, c));
+        let local_omega = match self.constants.smagorinsky {
+            Some(constant) => smagorinsky(constant, omega, c, directions, [packets, equilibrium]),
+            None => omega,
+        };
+        let source = (force != Vec3::ZERO).then(|| guo_forcing(c, directions, velocity, force));
+        let relaxed = self
+            .collider
+            .collide(packets, equilibrium, velocity, source, local_omega);
+        (relaxed, c * c * (1.0 / local_omega - 1.0 / omega))
+    }
+}
+
+fn check_collision<const Q: usize>(constants: &Constants) -> Result<(), UnsupportedCollision> {
+    match constants.collision.supports(Q) {
+        true => Ok(()),
+        false => Err(UnsupportedCollision {
+            collision: constants.collision,
+            directions: Q,
+        }),
+    }
+}
+
+/// Taylor expansion of the equilibrium distribution in one direction.
+fn equilibrium(
+    weight: Float,
+    density: Float,
+    flow_velocity: Vec3,
+    direction: Int3,
+    speed_of_sound: Float,
+) -> Float {
+    let direction_magnitude = flow_velocity.dot(direction.into());
+    let dm = direction_magnitude;
+    let c2 = speed_of_sound * speed_of_sound;
+    weight
+        * density
+        * (1.0 + dm / c2 + dm * dm / (2.0 * c2 * c2)
+            - flow_velocity.dot(flow_velocity) / (2.0 * c2))
+}
+
+/// Reflect a packet off a wall `proportion` of the way along its link.
+///
+/// `packets` holds the packet leaving the cell towards the wall, the packet
+/// moving the same way from the cell upstream and the packet already moving
+/// back at this cell. The upstream packet is `None` when that cell is not
+/// fluid, and a wall nearer than halfway then reflects as if it were halfway.
+/// `wall_momentum` is the momentum a moving wall would add to a packet
+/// reflected halfway along the link.
+fn reflect(
+    bounce_back: BounceBack,
+    proportion: Float,
+    (outgoing, behind, reflected): (Float, Option<Float>, Float),
+    wall_momentum: Float,
+) -> Float {
+    let q = proportion;
+    match bounce_back {
+        BounceBack::Halfway => outgoing - wall_momentum,
+        // Bouzidi, Firdaouss and Lallemand (2001).
+        BounceBack::Interpolated if q < 0.5 => match behind {
+            Some(behind) => 2.0 * q * outgoing + (1.0 - 2.0 * q) * behind - wall_momentum,
+            None => outgoing - wall_momentum,
+        },
+        BounceBack::Interpolated => {
+            (outgoing + (2.0 * q - 1.0) * reflected - wall_momentum) / (2.0 * q)
+        }
+    }
+}
+
+/// The extremes of the fluid returned by [`Simulation::stability`].
+#[derive(Clone, Copy, Debug)]
+pub struct Stability {
+    /// The fastest flow relative to the speed of sound, which is NaN once the
+    /// simulation has blown up.
+    pub max_mach: Float,
+    pub min_density: Float,
+    pub max_density: Float,
+    /// Negative packets are the first sign that the collision is failing.
+    pub min_packet: Float,
+}
+
+impl Stability {
+    /// Whether the flow is slow enough and the packets positive, which the
+    /// lattice Boltzmann method needs to stay accurate.
+    pub fn is_stable(&self) -> bool {
+        self.max_mach < 0.3 && self.min_density > 0.0 && self.min_packet >= 0.0
+    }
+}
+
+impl Display for Stability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Mach {:.3}, density {:.3}..{:.3}, min packet {:.2e}",
+            self.max_mach, self.min_density, self.max_density, self.min_packet
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BounceBack {
+    /// Reflect packets as if the wall sat halfway along every cut link.
+    Halfway,
+    /// Interpolate using the wall's actual position along the link, which is
+    /// second order accurate for curved and off-grid walls.
+    Interpolated,
+}
+
 #[derive(Clone, Copy)]
 pub struct Constants {
     pub time_relaxation_constant: Float,
     pub speed_of_sound: Float,
     pub particle_mass: Float,
     pub particle_velocity_decay: Float,
+    pub bounce_back: BounceBack,
+    pub collision: Collision,
+    /// The Smagorinsky constant of the subgrid turbulence model, usually
+    /// around 0.1 to 0.2. The model is off when `None`.
+    pub smagorinsky: Option<Float>,
+    /// Acceleration of rigid body meshes, which does not act on the fluid.
+    ///
+    /// Buoyancy is not included, so scale it down for bodies that are only
+    /// slightly denser than the fluid.
+    pub gravity: Vec3,
 }
 
 impl Default for Constants {
@@ -429,25 +928,312 @@ impl Default for Constants {
             speed_of_sound: 1.0 / Float::sqrt(3.0),
             particle_mass: 1.0,
             particle_velocity_decay: 0.95,
+            bounce_back: BounceBack::Halfway,
+            collision: Collision::Bgk,
+            smagorinsky: None,
+            gravity: Vec3::ZERO,
         }
     }
 }
 
-pub struct Particle<const X: usize, const Y: usize, const Z: usize> {
+pub struct Particle {
     pub position: Vec3,
     pub velocity: Vec3,
 }
 
-impl<const X: usize, const Y: usize, const Z: usize> Particle<X, Y, Z> {
-    pub fn from_rng_bounds<T: Rng>(rng: &mut T) -> Self {
+impl Particle {
+    pub fn from_rng_bounds<T: Rng>(rng: &mut T, size: Size3) -> Self {
         Self {
             position: (
-                rng.random_range(0..X) as f32,
-                rng.random_range(0..Y) as f32,
-                rng.random_range(0..Z) as f32,
+                rng.random_range(0..size.x) as Float,
+                rng.random_range(0..size.y) as Float,
+                rng.random_range(0..size.z) as Float,
             )
                 .into(),
             velocity: Vec3::ZERO,
         }
     }
 }
+
+#[cfg(test)]
+mod lbm_test {
+    use super::{
+        BounceBack, Constants, Field, MeshForce, Simulation, Simulation2d, VelocitySet, D2Q9,
+    };
+    use crate::{
+        math::{consts::TAU, Float, Int3, Size3, Vec3},
+        mesh::{Mesh, RigidBody},
+    };
+
+    // A thin slice of a channel between two walls that are off the grid.
+    const WIDTH: usize = 4;
+    const HEIGHT: usize = 14;
+    const WALLS: [Float; 2] = [2.3, 11.7];
+    const DISC_RADIUS: Float = 3.2;
+
+    /// A wall across the whole channel at height `y`.
+    fn wall(y: Float) -> Mesh {
+        Mesh::polyline(&[[-1.0, y], [WIDTH as Float + 1.0, y]], false)
+    }
+
+    /// The channel once it has settled, with the upper wall sliding along x
+    /// at `sliding` and the fluid pushed along x by `push` in every cell.
+    fn channel(
+        bounce_back: BounceBack,
+        walls: [Float; 2],
+        sliding: Float,
+        push: Float,
+    ) -> Simulation2d {
+        let constants = Constants {
+            bounce_back,
+            ..Default::default()
+        };
+        let mut meshes: Vec<_> = walls.map(wall).into();
+        meshes[1].velocity = Vec3::new(sliding, 0.0, 0.0);
+        let mut sim = Simulation2d::new_2d(WIDTH, HEIGHT, constants, vec![], meshes).unwrap();
+        sim.force = Field::new_from(sim.size, Vec3::new(push, 0.0, 0.0));
+        for _ in 0..6000 {
+            sim.step();
+        }
+        sim.calc_conditions();
+        sim
+    }
+
+    /// The speed along x at each height, averaged along the channel.
+    fn profile(sim: &Simulation2d) -> Vec<Float> {
+        (0..HEIGHT)
+            .map(|y| {
+                let speeds =
+                    (0..WIDTH).map(|x| sim.velocity.get(sim.size.bound(x, y, 0).unwrap()).x);
+                speeds.sum::<Float>() / WIDTH as Float
+            })
+            .collect()
+    }
+
+    /// A disc spinning anticlockwise at `spin` radians per step in the
+    /// middle of a box of fluid at rest, with a 24 sided polygon for its edge.
+    fn spinning_disc(spin: Float) -> Simulation2d {
+        let points: Vec<_> = (0..24)
+            .map(|i| {
+                let angle = TAU * i as Float / 24.0;
+                [
+                    10.0 + DISC_RADIUS * angle.cos(),
+                    10.0 + DISC_RADIUS * angle.sin(),
+                ]
+            })
+            .collect();
+        let mut disc = Mesh::polyline(&points, true);
+        disc.angular_velocity = Vec3::new(0.0, 0.0, spin);
+        Simulation2d::new_2d(20, 20, Constants::default(), vec![], vec![disc]).unwrap()
+    }
+
+    #[test]
+    fn walls_take_the_push_on_the_fluid() {
+        let push = 1e-5;
+        // Once the flow is steady, everything pushed into the fluid goes
+        // into the walls, on both sides of them.
+        let total = push * (WIDTH * HEIGHT) as Float;
+        for bounce_back in [BounceBack::Halfway, BounceBack::Interpolated] {
+            let sim = channel(bounce_back, WALLS, 0.0, push);
+            let force = sim
+                .mesh_forces
+                .iter()
+                .fold(Vec3::ZERO, |sum, mesh| sum + mesh.force);
+            assert!(
+                (force.x - total).abs() < 1e-3 * total && force.y.abs() < 1e-3 * total,
+                "{bounce_back:?} walls take {force}, not {total}"
+            );
+        }
+    }
+
+    #[test]
+    fn fluid_holds_back_a_spinning_disc() {
+        for spin in [0.004, -0.004] {
+            let mut sim = spinning_disc(spin);
+            // Before the swirl reaches the edges of the box, the fluid gains
+            // the angular momentum that the disc loses.
+            let mut lost = 0.0;
+            for _ in 0..20 {
+                sim.step();
+                let MeshForce { force, torque } = sim.mesh_forces[0];
+                assert!(torque.z * spin < 0.0, "{torque} against {spin}");
+                assert!(force.dot(force).sqrt() < 1e-3 * torque.z.abs(), "{force}");
+                lost -= torque.z;
+            }
+            sim.calc_conditions();
+            let voxels = sim.voxels.as_ref().unwrap();
+            let mut gained = 0.0;
+            for x in 0..sim.size.x {
+                for y in 0..sim.size.y {
+                    let loc = sim.size.bound(x, y, 0).unwrap();
+                    if !voxels.is_solid(loc) {
+                        let arm = Vec3::new(x as Float, y as Float, 0.0) - sim.meshes[0].center;
+                        gained += sim.density.get(loc) * arm.cross(*sim.velocity.get(loc)).z;
+                    }
+                }
+            }
+            assert!(
+                (gained - lost).abs() < 0.01 * lost.abs(),
+                "{gained} for {lost}"
+            );
+        }
+    }
+
+    #[test]
+    fn interpolated_walls_are_where_the_mesh_is() {
+        let push = 1e-5;
+        let [low, high] = WALLS;
+        // The relative error from the parabola between the walls.
+        let error = |bounce_back| {
+            let sim = channel(bounce_back, WALLS, 0.0, push);
+            let constants = sim.constants();
+            let c2 = constants.speed_of_sound * constants.speed_of_sound;
+            let viscosity = c2 * (1.0 / constants.time_relaxation_constant - 0.5);
+            let (mut error, mut norm) = (0.0, 0.0);
+            for (y, speed) in profile(&sim).into_iter().enumerate() {
+                let y = y as Float;
+                if low < y && y < high {
+                    let exact = push / (2.0 * viscosity) * (y - low) * (high - y);
+                    error += (speed - exact) * (speed - exact);
+                    norm += exact * exact;
+                }
+            }
+            (error / norm).sqrt()
+        };
+        let (halfway, interpolated) = (error(BounceBack::Halfway), error(BounceBack::Interpolated));
+        assert!(
+            interpolated < 0.03 && interpolated < halfway / 4.0,
+            "{interpolated} interpolated against {halfway} halfway"
+        );
+    }
+
+    #[test]
+    fn interpolating_halfway_is_halfway_bounce_back() {
+        let walls = [2.5, 11.5];
+        let halfway = channel(BounceBack::Halfway, walls, 0.0, 1e-5);
+        let interpolated = channel(BounceBack::Interpolated, walls, 0.0, 1e-5);
+        for x in 0..WIDTH {
+            for y in 0..HEIGHT {
+                let loc = halfway.size.bound(x, y, 0).unwrap();
+                assert_eq!(
+                    halfway.distributions.cell(loc),
+                    interpolated.distributions.cell(loc),
+                    "at {loc:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sliding_wall_shears_the_channel_evenly() {
+        let sliding = 0.01;
+        let [low, high] = WALLS;
+        for (bounce_back, tolerance) in [
+            (BounceBack::Interpolated, 1e-4),
+            // As if the walls were on the closest link midpoints.
+            (BounceBack::Halfway, 0.03),
+        ] {
+            let sim = channel(bounce_back, WALLS, sliding, 0.0);
+            for (y, speed) in profile(&sim).into_iter().enumerate() {
+                let y = y as Float;
+                if low < y && y < high {
+                    let exact = sliding * (y - low) / (high - low);
+                    assert!(
+                        (speed - exact).abs() < tolerance * sliding,
+                        "{bounce_back:?} gives {speed} at {y}, not {exact}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spinning_disc_drags_the_fluid_around() {
+        let spin = 0.004;
+        let mut sim = spinning_disc(spin);
+        for _ in 0..400 {
+            sim.step();
+        }
+        sim.calc_conditions();
+        // Around a cylinder in open fluid the speed falls off as 1 / r,
+        // which the box changes only slightly this close to the disc.
+        let center = sim.meshes[0].center;
+        for (x, y) in [(15, 10), (10, 15), (5, 10), (10, 5)] {
+            let arm = Vec3::new(x as Float, y as Float, 0.0) - center;
+            let r = arm.dot(arm).sqrt();
+            let around = Vec3::new(-arm.y, arm.x, 0.0) / r;
+            let speed = around.dot(*sim.velocity.get(sim.size.bound(x, y, 0).unwrap()));
+            let open = spin * DISC_RADIUS * DISC_RADIUS / r;
+            assert!(
+                (speed - open).abs() < 0.1 * open,
+                "{speed} at ({x}, {y}), not {open}"
+            );
+        }
+    }
+
+    #[test]
+    fn uncovered_cells_move_with_the_mesh() {
+        let inertia = RigidBody::box_inertia(1e6, Vec3::new(2.0, 2.0, 2.0));
+        let mut mesh = Mesh::cuboid(Vec3::new(2.5, 2.5, 2.5), Vec3::new(4.5, 4.5, 4.5))
+            .with_rigid_body(1e6, inertia);
+        mesh.velocity = Vec3::new(0.3, 0.0, 0.0);
+        let size = Size3::new(12, 8, 8);
+        let mut sim = Simulation::new(size, Constants::default(), vec![], vec![mesh]);
+        let loc = size.bound(3, 3, 3).unwrap();
+        sim.step();
+        assert!(sim.voxels.as_ref().unwrap().is_solid(loc));
+        // The back of the block passes the cell in the second step.
+        sim.step();
+        assert!(!sim.voxels.as_ref().unwrap().is_solid(loc));
+        let velocity = *sim.velocity.get(loc);
+        assert!(velocity.approx_eq(sim.meshes[0].velocity), "{velocity}");
+        // As dense as the fluid around it, in the wake of the block.
+        let density: Float = sim.distributions.cell(loc).iter().sum();
+        assert!((density - *sim.density.get(loc)).abs() < 1e-6);
+        let around: Vec<Float> = [(2, 2), (2, 3), (2, 4), (3, 2), (4, 2)]
+            .map(|(x, y)| *sim.density.get(size.bound(x, y, 3).unwrap()))
+            .into();
+        let (min, max) = around
+            .iter()
+            .fold((Float::MAX, Float::MIN), |(min, max), d| {
+                (min.min(*d), max.max(*d))
+            });
+        assert!(
+            min <= density && density <= max,
+            "{density} not in {min}..{max}"
+        );
+    }
+
+    #[test]
+    fn stability_is_measured_from_the_packets() {
+        let mut sim = Simulation2d::new_2d(6, 6, Constants::default(), vec![], vec![]).unwrap();
+        sim.step();
+        assert!(sim.stability().is_stable());
+        // Knock one cell about after the step, leaving the conditions stale.
+        let loc = sim.size.bound(2, 3, 0).unwrap();
+        let east = D2Q9::DIRECTIONS
+            .iter()
+            .position(|(dir, _)| *dir == Int3::new(1, 0, 0))
+            .unwrap();
+        let mut fast = sim.distributions.cell(loc);
+        fast[east] += 1.0;
+        sim.distributions.set_cell(loc, fast);
+        let stability = sim.stability();
+        assert!(
+            stability.max_mach > 0.3 && !stability.is_stable(),
+            "{stability}"
+        );
+        let mut negative = fast;
+        negative[east] = -0.01;
+        sim.distributions.set_cell(loc, negative);
+        let stability = sim.stability();
+        assert!(
+            stability.min_packet < 0.0 && !stability.is_stable(),
+            "{stability}"
+        );
+        let mut blown_up = negative;
+        blown_up[east] = Float::NAN;
+        sim.distributions.set_cell(loc, blown_up);
+        assert!(!sim.stability().is_stable());
+    }
+}
@@ -0,0 +1,939 @@
+use std::fmt::Display;
+
+use crate::{
+    lbm::VelocitySet,
+    math::{lerp, Int3, Vec3},
+    Float,
+};
+
+/// How the packets at each point relax towards equilibrium.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Collision {
+    /// Relax every packet at `time_relaxation_constant`.
+    Bgk,
+    /// Relax each moment of the packets at its own rate, which stays stable
+    /// at much lower viscosities than [`Collision::Bgk`].
+    ///
+    /// The moments are those of [`D3Q19`](super::D3Q19), which is the only
+    /// lattice this works on.
+    Mrt(MrtRates),
+    /// Relax the parts of each pair of opposite packets that are even and odd
+    /// in direction at separate rates.
+    ///
+    /// The even part relaxes at `time_relaxation_constant`, and `magic` is
+    /// Ginzburg's Λ, which sets the odd rate. Bounce-back walls stay at the
+    /// same place for every viscosity when `magic` is fixed, and sit exactly
+    /// halfway along the links at `3 / 16`.
+    Trt { magic: Float },
+    /// Relax the moments in the frame moving with the flow towards those of
+    /// the Maxwell distribution, which stays stable at much higher Reynolds
+    /// numbers than [`Collision::Bgk`].
+    ///
+    /// The deviatoric stresses relax at `time_relaxation_constant`, the
+    /// pressure at `bulk` and the third and fourth order moments at `higher`.
+    /// Both are usually 1. Like [`Collision::Mrt`], this only works on
+    /// [`D3Q19`](super::D3Q19).
+    CentralMoment { bulk: Float, higher: Float },
+    /// Keep only the part of each packet's distance from equilibrium that
+    /// comes from the stress before relaxing like [`Collision::Bgk`], which
+    /// damps the higher order noise that makes BGK blow up (Latt and Chopard
+    /// 2006).
+    Regularized,
+    /// Entropic BGK (ELBM), which stretches each relaxation so that the
+    /// entropy of the packets never decreases (Karlin et al. 1999).
+    ///
+    /// This is the most robust choice but adds a little viscosity wherever
+    /// the flow is under-resolved.
+    Entropic,
+}
+
+impl Collision {
+    /// Whether this works on a lattice with `directions` directions, which
+    /// must be [`D3Q19`](super::D3Q19) for the operators built on its
+    /// moments.
+    pub fn supports(&self, directions: usize) -> bool {
+        !matches!(self, Self::Mrt(_) | Self::CentralMoment { .. }) || directions == 19
+    }
+}
+
+/// A [`Collision`] given to a simulation whose lattice it does not work on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnsupportedCollision {
+    pub collision: Collision,
+    /// The number of directions of the simulation's lattice.
+    pub directions: usize,
+}
+
+impl Display for UnsupportedCollision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self.collision {
+            Collision::Mrt(_) => "MRT",
+            Collision::CentralMoment { .. } => "central moment",
+            _ => "this",
+        };
+        write!(
+            f,
+            "{name} collision needs the D3Q19 lattice, not one with {} directions",
+            self.directions
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedCollision {}
+
+/// Relaxation rates for [`Collision::Mrt`], following d'Humières et al. (2002).
+///
+/// The shear stresses relax at `time_relaxation_constant`, which sets the
+/// viscosity. Density and momentum are conserved, and the other moments only
+/// change the bulk viscosity and the stability.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MrtRates {
+    /// The kinetic energy, which sets the bulk viscosity.
+    pub energy: Float,
+    /// The square of the kinetic energy.
+    pub energy_square: Float,
+    /// The energy flux.
+    pub heat_flux: Float,
+    /// The fourth order moments that mirror the shear stresses.
+    pub ghost_stress: Float,
+    /// The third order moments that mirror the energy flux.
+    pub ghost_flux: Float,
+}
+
+impl Default for MrtRates {
+    fn default() -> Self {
+        Self {
+            energy: 1.19,
+            energy_square: 1.4,
+            heat_flux: 1.2,
+            ghost_stress: 1.4,
+            ghost_flux: 1.98,
+        }
+    }
+}
+
+/// A [`Collision`] prepared for one step.
+pub(super) enum Collider<const Q: usize> {
+    Bgk,
+    Mrt(Box<Mrt<Q>>),
+    Trt(Trt<Q>),
+    CentralMoment(Box<CentralMoment<Q>>),
+    /// The directions, with the square of the speed of sound.
+    Regularized([(Int3, Float); Q], Float),
+    Entropic([(Int3, Float); Q]),
+}
+
+impl<const Q: usize> Collider<Q> {
+    /// Prepare `collision` for a lattice of velocity set `V` with a speed of
+    /// sound `speed_of_sound`.
+    pub fn new<V: VelocitySet<Q>>(collision: Collision, speed_of_sound: Float) -> Self {
+        let c2 = speed_of_sound * speed_of_sound;
+        let directions = &V::DIRECTIONS;
+        match collision {
+            Collision::Bgk => Self::Bgk,
+            Collision::Mrt(rates) => Self::Mrt(Box::new(Mrt::new(directions, rates))),
+            Collision::Trt { magic } => Self::Trt(Trt::new(V::OPPOSITES, magic)),
+            Collision::CentralMoment { bulk, higher } => {
+                let rates = [bulk, higher];
+                Self::CentralMoment(Box::new(CentralMoment::new(directions, rates, c2)))
+            }
+            Collision::Regularized => Self::Regularized(*directions, c2),
+            Collision::Entropic => Self::Entropic(*directions),
+        }
+    }
+
+    /// Relax `packets` at one point towards `equilibrium` at `velocity`,
+    /// with the shear stresses relaxing at `omega`, and add the [`guo_forcing`]
+    /// `source` of any body force.
+    ///
+    /// Like `equilibrium`, `velocity` includes half of the body force. Each
+    /// operator scales the source by one less half of the rate of each of its
+    /// moments, so that the momentum grows by exactly the force.
+    pub fn collide(
+        &self,
+        packets: [Float; Q],
+        equilibrium: [Float; Q],
+        velocity: Vec3,
+        source: Option<[Float; Q]>,
+        omega: Float,
+    ) -> [Float; Q] {
+        let scaled = |i: usize, rate: Float| source.map_or(0.0, |s| (1.0 - rate / 2.0) * s[i]);
+        match self {
+            // Wikipedia uses
+            // lerp(current, equilibrium, (TRC-1)/TRC)
+            // where TRC=time_relaxation_constant
+            Self::Bgk => {
+                std::array::from_fn(|i| lerp(packets[i], equilibrium[i], omega) + scaled(i, omega))
+            }
+            Self::Mrt(mrt) => mrt.collide(packets, equilibrium, source, omega),
+            Self::Trt(trt) => trt.collide(packets, equilibrium, source, omega),
+            Self::CentralMoment(central) => {
+                central.collide(packets, equilibrium, velocity, source, omega)
+            }
+            Self::Regularized(directions, c2) => {
+                // Half of the source is already in the packets, as half of the
+                // force is in the velocity. The rest of the packets are reset
+                // to equilibrium, which is a rate of 1.
+                let shifted: [Float; Q] = std::array::from_fn(|i| packets[i] + scaled(i, 1.0));
+                let off = regularize(directions, *c2, shifted, equilibrium);
+                std::array::from_fn(|i| equilibrium[i] + (1.0 - omega) * off[i] + scaled(i, 1.0))
+            }
+            Self::Entropic(directions) => {
+                let alpha = entropic_stretch(directions, packets, equilibrium);
+                let rate = alpha * omega / 2.0;
+                std::array::from_fn(|i| lerp(packets[i], equilibrium[i], rate) + scaled(i, rate))
+            }
+        }
+    }
+}
+
+/// The distance of `packets` from `equilibrium` rebuilt from its stress
+/// alone, on a lattice whose speed of sound squared is `c2`.
+fn regularize<const Q: usize>(
+    directions: &[(Int3, Float); Q],
+    c2: Float,
+    packets: [Float; Q],
+    equilibrium: [Float; Q],
+) -> [Float; Q] {
+    let axes = |dir: Int3| [dir.x, dir.y, dir.z].map(|v| v as Float);
+    let mut stress = [[0.0; 3]; 3];
+    for ((dir, _), (packet, equilibrium)) in directions.iter().zip(packets.iter().zip(equilibrium))
+    {
+        let c = axes(*dir);
+        for a in 0..3 {
+            for b in 0..3 {
+                stress[a][b] += c[a] * c[b] * (packet - equilibrium);
+            }
+        }
+    }
+    directions.map(|(dir, weight)| {
+        let c = axes(dir);
+        let mut projected = 0.0;
+        for a in 0..3 {
+            for b in 0..3 {
+                let delta = if a == b { c2 } else { 0.0 };
+                projected += (c[a] * c[b] - delta) * stress[a][b];
+            }
+        }
+        weight / (2.0 * c2 * c2) * projected
+    })
+}
+
+/// The H function of the entropic step, the sum of f ln(f / w) over the
+/// packets.
+fn entropy<const Q: usize>(directions: &[(Int3, Float); Q], packets: [Float; Q]) -> Float {
+    packets
+        .iter()
+        .zip(directions)
+        .map(|(f, (_, weight))| f * (f / weight).ln())
+        .sum()
+}
+
+/// The α of the entropic step, where moving `packets` α times as far as
+/// `equilibrium` keeps the entropy the same.
+///
+/// BGK corresponds to α = 2, which is returned when the packets are too close
+/// to equilibrium to tell.
+fn entropic_stretch<const Q: usize>(
+    directions: &[(Int3, Float); Q],
+    packets: [Float; Q],
+    equilibrium: [Float; Q],
+) -> Float {
+    let delta: [Float; Q] = std::array::from_fn(|i| equilibrium[i] - packets[i]);
+    let spread = delta
+        .iter()
+        .zip(packets)
+        .map(|(d, f)| (d / f).abs())
+        .fold(0.0, Float::max);
+    if !spread.is_finite() || spread < 1e-4 {
+        return 2.0;
+    }
+    // Every packet must stay positive for the entropy to exist.
+    let limit = delta
+        .iter()
+        .zip(packets)
+        .filter(|(d, _)| **d < 0.0)
+        .map(|(d, f)| -f / d)
+        .fold(Float::INFINITY, Float::min);
+    let start = entropy(directions, packets);
+    let mut alpha: Float = Float::min(2.0, 0.99 * limit);
+    for _ in 0..20 {
+        let moved: [Float; Q] = std::array::from_fn(|i| packets[i] + alpha * delta[i]);
+        let error = entropy(directions, moved) - start;
+        let slope: Float = moved
+            .iter()
+            .zip(delta)
+            .zip(directions)
+            .map(|((f, d), (_, weight))| d * ((f / weight).ln() + 1.0))
+            .sum();
+        let step = error / slope;
+        alpha = (alpha - step).min(0.99 * limit);
+        if !alpha.is_finite() || alpha <= 1.0 {
+            return Float::min(2.0, 0.99 * limit);
+        }
+        if step.abs() < 1e-6 {
+            break;
+        }
+    }
+    alpha
+}
+
+/// The packets that a body force `force` adds in one step, following Guo,
+/// Zheng and Shi (2002), before [`Collider::collide`] scales them for the
+/// relaxation.
+///
+/// `velocity` must already include half of the force.
+pub(super) fn guo_forcing<const Q: usize>(
+    speed_of_sound: Float,
+    directions: &[(Int3, Float); Q],
+    velocity: Vec3,
+    force: Vec3,
+) -> [Float; Q] {
+    let c2 = speed_of_sound * speed_of_sound;
+    directions.map(|(dir, weight)| {
+        let dir = Vec3::from(dir);
+        let shape = (dir - velocity) / c2 + dir.dot(velocity) / (c2 * c2) * dir;
+        weight * shape.dot(force)
+    })
+}
+
+/// The relaxation rate that adds the Smagorinsky eddy viscosity to the
+/// viscosity set by `omega`, following Hou et al. (1996).
+///
+/// The strain rate comes from the stress carried by the packets that are off
+/// equilibrium, so no velocity gradients are needed.
+pub(super) fn smagorinsky<const Q: usize>(
+    constant: Float,
+    omega: Float,
+    speed_of_sound: Float,
+    directions: &[(Int3, Float); Q],
+    [packets, equilibrium]: [[Float; Q]; 2],
+) -> Float {
+    let mut stress = [[0.0; 3]; 3];
+    let mut density = 0.0;
+    for ((dir, _), (packet, equilibrium)) in directions.iter().zip(packets.iter().zip(equilibrium))
+    {
+        let c = [dir.x, dir.y, dir.z].map(|v| v as Float);
+        for a in 0..3 {
+            for b in 0..3 {
+                stress[a][b] += c[a] * c[b] * (packet - equilibrium);
+            }
+        }
+        density += packet;
+    }
+    let magnitude = stress
+        .as_flattened()
+        .iter()
+        .map(|v| v * v)
+        .sum::<Float>()
+        .sqrt();
+    let c2 = speed_of_sound * speed_of_sound;
+    let tau = 1.0 / omega;
+    let tau = (tau
+        + (tau * tau
+            + 2.0 * Float::sqrt(2.0) * constant * constant * magnitude / (density * c2 * c2))
+            .sqrt())
+        / 2.0;
+    1.0 / tau
+}
+
+/// The opposite of each direction, with the magic parameter that ties the
+/// rate of the odd part of each pair to that of the even part.
+pub(super) struct Trt<const Q: usize> {
+    opposites: [usize; Q],
+    magic: Float,
+}
+
+impl<const Q: usize> Trt<Q> {
+    pub fn new(opposites: [usize; Q], magic: Float) -> Self {
+        Self { opposites, magic }
+    }
+
+    /// The rate of the odd parts when the even parts relax at `even`.
+    fn odd(&self, even: Float) -> Float {
+        // Λ = (1 / even - 1 / 2) (1 / odd - 1 / 2)
+        1.0 / (self.magic / (1.0 / even - 0.5) + 0.5)
+    }
+
+    /// Relax `packets` towards `equilibrium`, adding each part of `source`
+    /// scaled for the rate of that part.
+    pub fn collide(
+        &self,
+        packets: [Float; Q],
+        equilibrium: [Float; Q],
+        source: Option<[Float; Q]>,
+        even: Float,
+    ) -> [Float; Q] {
+        let odd = self.odd(even);
+        let source = source.unwrap_or([0.0; Q]);
+        std::array::from_fn(|i| {
+            let j = self.opposites[i];
+            let off_even = (packets[i] + packets[j] - equilibrium[i] - equilibrium[j]) / 2.0;
+            let off_odd = (packets[i] - packets[j] - equilibrium[i] + equilibrium[j]) / 2.0;
+            let source_even = (source[i] + source[j]) / 2.0;
+            let source_odd = (source[i] - source[j]) / 2.0;
+            packets[i] - even * off_even - odd * off_odd
+                + (1.0 - even / 2.0) * source_even
+                + (1.0 - odd / 2.0) * source_odd
+        })
+    }
+}
+
+/// The powers of x, y and z in each moment used by [`CentralMoment`], in the
+/// order density, momentum, normal stresses, shear stresses, third order then
+/// fourth order.
+///
+/// Every moment made from lower powers is also in the list, so shifting the
+/// moments between frames never needs one that is missing.
+const CENTRAL_POWERS: [[i32; 3]; 19] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [0, 1, 0],
+    [0, 0, 1],
+    [2, 0, 0],
+    [0, 2, 0],
+    [0, 0, 2],
+    [1, 1, 0],
+    [1, 0, 1],
+    [0, 1, 1],
+    [1, 2, 0],
+    [1, 0, 2],
+    [2, 1, 0],
+    [0, 1, 2],
+    [2, 0, 1],
+    [0, 2, 1],
+    [2, 2, 0],
+    [2, 0, 2],
+    [0, 2, 2],
+];
+
+/// Cascaded collision in the style of Premnath and Banerjee (2011).
+///
+/// The moments are those of the D3Q19 lattice.
+pub(super) struct CentralMoment<const Q: usize> {
+    /// The square of the speed of sound.
+    c2: Float,
+    directions: [Vec3; Q],
+    /// [`CENTRAL_POWERS`], one for each direction.
+    powers: [[i32; 3]; Q],
+    /// Turns the raw moments of `powers` back into packets.
+    inverse: [[Float; Q]; Q],
+    bulk: Float,
+    higher: Float,
+}
+
+impl<const Q: usize> CentralMoment<Q> {
+    pub fn new(directions: &[(Int3, Float); Q], [bulk, higher]: [Float; 2], c2: Float) -> Self {
+        let directions = directions.map(|(dir, _)| Vec3::from(dir));
+        let powers: [[i32; 3]; Q] = std::array::from_fn(|k| CENTRAL_POWERS[k]);
+        let raw = powers.map(|powers| directions.map(|c| monomial(c, powers)));
+        Self {
+            c2,
+            directions,
+            powers,
+            inverse: invert(raw),
+            bulk,
+            higher,
+        }
+    }
+
+    /// Relax the central moments of `packets` in the frame moving at
+    /// `velocity`, adding the moments of `source` scaled for the rate of each.
+    ///
+    /// The stresses relax towards those of `equilibrium`, and the third and
+    /// fourth order moments towards those of the Maxwell distribution, which
+    /// has none of the former and a c⁴ fourth order moment.
+    pub fn collide(
+        &self,
+        packets: [Float; Q],
+        equilibrium: [Float; Q],
+        velocity: Vec3,
+        source: Option<[Float; Q]>,
+        shear: Float,
+    ) -> [Float; Q] {
+        let density: Float = packets.iter().sum();
+        let mut target = self.central(equilibrium, velocity);
+        target[10..16].fill(0.0);
+        target[16..19].fill(density * self.c2 * self.c2);
+        let mut central = self.relax(self.central(packets, velocity), target, shear);
+        if let Some(source) = source {
+            // Scaling by one less half the rate is the average of the moments
+            // as they are and relaxed to nothing.
+            let source = self.central(source, velocity);
+            let relaxed = self.relax(source, [0.0; Q], shear);
+            for (value, (source, relaxed)) in central.iter_mut().zip(source.iter().zip(relaxed)) {
+                *value += (source + relaxed) / 2.0;
+            }
+        }
+
+        // Shift back to the rest frame with the binomial expansion of
+        // (c - u + u)^n.
+        let u = [velocity.x, velocity.y, velocity.z];
+        let raw = self.powers.map(|powers| {
+            self.powers
+                .iter()
+                .zip(central)
+                .filter(|(lower, _)| (0..3).all(|a| lower[a] <= powers[a]))
+                .map(|(lower, value)| {
+                    let shift = (0..3)
+                        .map(|a| {
+                            let [n, k] = [powers[a], lower[a]];
+                            binomial(n, k) * u[a].powi(n - k)
+                        })
+                        .product::<Float>();
+                    shift * value
+                })
+                .sum::<Float>()
+        });
+        self.inverse
+            .map(|row| row.iter().zip(raw).map(|(weight, m)| weight * m).sum())
+    }
+
+    /// Relax `central` moments towards `target`, leaving the density and
+    /// momentum as they are.
+    fn relax(&self, mut central: [Float; Q], target: [Float; Q], shear: Float) -> [Float; Q] {
+        let relax =
+            |value: Float, equilibrium: Float, rate: Float| value + rate * (equilibrium - value);
+        let [xx, yy, zz] = [central[4], central[5], central[6]];
+        let [xx_eq, yy_eq, zz_eq] = [target[4], target[5], target[6]];
+        let trace = relax(xx + yy + zz, xx_eq + yy_eq + zz_eq, self.bulk);
+        let dxy = relax(xx - yy, xx_eq - yy_eq, shear);
+        let dxz = relax(xx - zz, xx_eq - zz_eq, shear);
+        central[4] = (trace + dxy + dxz) / 3.0;
+        central[5] = (trace - 2.0 * dxy + dxz) / 3.0;
+        central[6] = (trace + dxy - 2.0 * dxz) / 3.0;
+        for (value, equilibrium) in central[7..10].iter_mut().zip(&target[7..10]) {
+            *value = relax(*value, *equilibrium, shear);
+        }
+        for (value, equilibrium) in central[10..19].iter_mut().zip(&target[10..19]) {
+            *value = relax(*value, *equilibrium, self.higher);
+        }
+        central
+    }
+
+    /// The moments of `packets` in the frame moving at `velocity`.
+    fn central(&self, packets: [Float; Q], velocity: Vec3) -> [Float; Q] {
+        self.powers.map(|powers| {
+            packets
+                .iter()
+                .zip(self.directions)
+                .map(|(packet, dir)| packet * monomial(dir - velocity, powers))
+                .sum::<Float>()
+        })
+    }
+}
+
+fn monomial(c: Vec3, [a, b, d]: [i32; 3]) -> Float {
+    c.x.powi(a) * c.y.powi(b) * c.z.powi(d)
+}
+
+fn binomial(n: i32, k: i32) -> Float {
+    match (n, k) {
+        (2, 1) => 2.0,
+        _ => 1.0,
+    }
+}
+
+/// Gauss-Jordan elimination with partial pivoting.
+fn invert<const Q: usize>(mut matrix: [[Float; Q]; Q]) -> [[Float; Q]; Q] {
+    let mut inverse: [[Float; Q]; Q] =
+        std::array::from_fn(|i| std::array::from_fn(|j| if i == j { 1.0 } else { 0.0 }));
+    for column in 0..Q {
+        let pivot = (column..Q)
+            .max_by(|a, b| {
+                matrix[*a][column]
+                    .abs()
+                    .total_cmp(&matrix[*b][column].abs())
+            })
+            .unwrap();
+        matrix.swap(column, pivot);
+        inverse.swap(column, pivot);
+        let scale = matrix[column][column];
+        for j in 0..Q {
+            matrix[column][j] /= scale;
+            inverse[column][j] /= scale;
+        }
+        for row in 0..Q {
+            let factor = matrix[row][column];
+            if row == column || factor == 0.0 {
+                continue;
+            }
+            for j in 0..Q {
+                matrix[row][j] -= factor * matrix[column][j];
+                inverse[row][j] -= factor * inverse[column][j];
+            }
+        }
+    }
+    inverse
+}
+
+/// The orthogonal moment basis of the D3Q19 lattice, with a rate for each
+/// moment.
+pub(super) struct Mrt<const Q: usize> {
+    /// The weight of each packet in each moment.
+    basis: [[Float; Q]; Q],
+    /// The squared length of each row of `basis`, which inverts it.
+    norms: [Float; Q],
+    /// `None` for the shear stresses, which relax at the rate given to
+    /// [`Mrt::collide`].
+    rates: [Option<Float>; Q],
+}
+
+impl<const Q: usize> Mrt<Q> {
+    pub fn new(directions: &[(Int3, Float); Q], rates: MrtRates) -> Self {
+        let columns = directions.map(|(dir, _)| moments(dir));
+        let basis: [[Float; Q]; Q] = std::array::from_fn(|k| columns.map(|m| m[k]));
+        let norms = basis.map(|row| row.iter().map(|v| v * v).sum());
+        let MrtRates {
+            energy: e,
+            energy_square: e2,
+            heat_flux: q,
+            ghost_stress: pi,
+            ghost_flux: m,
+        } = rates;
+        let s = None;
+        let rates = [
+            Some(0.0),
+            Some(e),
+            Some(e2),
+            Some(0.0),
+            Some(q),
+            Some(0.0),
+            Some(q),
+            Some(0.0),
+            Some(q),
+            s,
+            Some(pi),
+            s,
+            Some(pi),
+            s,
+            s,
+            s,
+            Some(m),
+            Some(m),
+            Some(m),
+        ];
+        Self {
+            basis,
+            norms,
+            rates: std::array::from_fn(|k| rates[k]),
+        }
+    }
+
+    /// Relax the moments of `packets` towards those of `equilibrium`,
+    /// adding the moments of `source` scaled for the rate of each.
+    pub fn collide(
+        &self,
+        packets: [Float; Q],
+        equilibrium: [Float; Q],
+        source: Option<[Float; Q]>,
+        shear: Float,
+    ) -> [Float; Q] {
+        let mut relaxed = packets;
+        for ((row, norm), rate) in self.basis.iter().zip(self.norms).zip(self.rates) {
+            let rate = rate.unwrap_or(shear);
+            let off: Float = (0..Q).map(|i| row[i] * (packets[i] - equilibrium[i])).sum();
+            let forced: Float =
+                source.map_or(0.0, |source| (0..Q).map(|i| row[i] * source[i]).sum());
+            let change = (rate * off - (1.0 - rate / 2.0) * forced) / norm;
+            for (value, weight) in relaxed.iter_mut().zip(row) {
+                *value -= change * weight;
+            }
+        }
+        relaxed
+    }
+}
+
+/// The weight of a packet moving in `dir` in each moment, in the order
+/// density, energy, energy square, then x, y and z momentum each followed by
+/// its energy flux, the normal and fourth order stresses, the shear stresses
+/// and the third order ghost moments.
+fn moments(dir: Int3) -> [Float; 19] {
+    let Vec3 { x, y, z } = dir.into();
+    let c2 = x * x + y * y + z * z;
+    let flux = 5.0 * c2 - 9.0;
+    let ghost = 3.0 * c2 - 5.0;
+    let xx = 3.0 * x * x - c2;
+    let ww = y * y - z * z;
+    [
+        1.0,
+        19.0 * c2 - 30.0,
+        (21.0 * c2 * c2 - 53.0 * c2 + 24.0) / 2.0,
+        x,
+        flux * x,
+        y,
+        flux * y,
+        z,
+        flux * z,
+        xx,
+        ghost * xx,
+        ww,
+        ghost * ww,
+        x * y,
+        y * z,
+        x * z,
+        x * (y * y - z * z),
+        y * (z * z - x * x),
+        z * (x * x - y * y),
+    ]
+}
+
+#[cfg(test)]
+mod collision_test {
+    use super::{
+        entropic_stretch, entropy, guo_forcing, regularize, smagorinsky, CentralMoment, Collider,
+        Collision, Mrt, MrtRates, Trt,
+    };
+    use crate::{
+        approx_eq,
+        lbm::{
+            equilibrium, fluid_velocity, moments, Constants, Simulation, Simulation2d, VelocitySet,
+            D3Q19,
+        },
+        math::{Size3, Vec3},
+        Float,
+    };
+
+    #[test]
+    fn basis_is_orthogonal() {
+        let mrt = Mrt::new(&D3Q19::DIRECTIONS, MrtRates::default());
+        for (k, a) in mrt.basis.iter().enumerate() {
+            for b in &mrt.basis[k + 1..] {
+                let dot: Float = a.iter().zip(b).map(|(a, b)| a * b).sum();
+                assert!(approx_eq(dot, 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn equal_rates_match_bgk() {
+        let directions = D3Q19::DIRECTIONS;
+        let omega = 1.3;
+        let rates = MrtRates {
+            energy: omega,
+            energy_square: omega,
+            heat_flux: omega,
+            ghost_stress: omega,
+            ghost_flux: omega,
+        };
+        let mrt = Mrt::new(&directions, rates);
+        // Disturb the equilibrium without changing density or momentum.
+        let equilibrium = directions.map(|(_, weight)| weight);
+        let packets: [Float; 19] = std::array::from_fn(|i| {
+            equilibrium[i] + 0.002 * mrt.basis[1][i] + 0.01 * mrt.basis[9][i]
+        });
+        for (i, value) in mrt
+            .collide(packets, equilibrium, None, omega)
+            .iter()
+            .enumerate()
+        {
+            let bgk = packets[i] + omega * (equilibrium[i] - packets[i]);
+            assert!(approx_eq(*value, bgk));
+        }
+    }
+
+    #[test]
+    fn trt_matches_bgk_at_equal_rates() {
+        let directions = D3Q19::DIRECTIONS;
+        let omega: Float = 1.3;
+        let magic = (1.0 / omega - 0.5).powi(2);
+        let trt = Trt::new(D3Q19::OPPOSITES, magic);
+        assert!(approx_eq(trt.odd(omega), omega));
+        let bgk = Collider::new::<D3Q19>(Collision::Bgk, 1.0 / Float::sqrt(3.0));
+        let equilibrium = directions.map(|(_, weight)| weight);
+        let packets: [Float; 19] = std::array::from_fn(|i| equilibrium[i] + 0.001 * i as Float);
+        let expected = bgk.collide(packets, equilibrium, Vec3::ZERO, None, omega);
+        for (value, expected) in trt
+            .collide(packets, equilibrium, None, omega)
+            .iter()
+            .zip(expected)
+        {
+            assert!(approx_eq(*value, expected));
+        }
+    }
+
+    #[test]
+    fn trt_conserves_mass_and_momentum() {
+        let directions = D3Q19::DIRECTIONS;
+        let trt = Trt::new(D3Q19::OPPOSITES, 3.0 / 16.0);
+        let packets: [Float; 19] =
+            std::array::from_fn(|i| directions[i].1 * (1.0 + 0.05 * (i % 5) as Float));
+        let (density, momentum) = moments(&directions, &packets);
+        let equilibrium = directions.map(|(dir, weight)| {
+            equilibrium(
+                weight,
+                density,
+                momentum / density,
+                dir,
+                1.0 / Float::sqrt(3.0),
+            )
+        });
+        let relaxed = trt.collide(packets, equilibrium, None, 1.7);
+        let (new_density, new_momentum) = moments(&directions, &relaxed);
+        assert!(approx_eq(new_density, density));
+        assert!(approx_eq(
+            (new_momentum - momentum).dot(new_momentum - momentum),
+            0.0
+        ));
+    }
+
+    #[test]
+    fn central_moment_keeps_rest_equilibrium() {
+        let directions = D3Q19::DIRECTIONS;
+        let central = CentralMoment::new(&directions, [1.0, 1.0], 1.0 / 3.0);
+        let equilibrium = directions.map(|(_, weight)| 1.2 * weight);
+        let relaxed = central.collide(equilibrium, equilibrium, Vec3::ZERO, None, 1.6);
+        for (value, expected) in relaxed.iter().zip(equilibrium) {
+            assert!(approx_eq(*value, expected));
+        }
+    }
+
+    #[test]
+    fn central_moment_conserves_mass_and_momentum() {
+        let directions = D3Q19::DIRECTIONS;
+        let c = 1.0 / Float::sqrt(3.0);
+        let central = CentralMoment::new(&directions, [1.2, 1.0], c * c);
+        let packets: [Float; 19] =
+            std::array::from_fn(|i| directions[i].1 * (1.0 + 0.05 * (i % 7) as Float));
+        let (density, momentum) = moments(&directions, &packets);
+        // The frame may move a little faster than the packets, as it does
+        // under a body force.
+        let velocity = momentum / density + Vec3::new(1e-3, 0.0, -2e-3);
+        let equilibrium =
+            directions.map(|(dir, weight)| equilibrium(weight, density, velocity, dir, c));
+        let relaxed = central.collide(packets, equilibrium, velocity, None, 1.8);
+        let (new_density, new_momentum) = moments(&directions, &relaxed);
+        assert!(approx_eq(new_density, density));
+        assert!(approx_eq(
+            (new_momentum - momentum).dot(new_momentum - momentum),
+            0.0
+        ));
+    }
+
+    #[test]
+    fn moment_collisions_need_d3q19() {
+        let mrt = Constants {
+            collision: Collision::Mrt(MrtRates::default()),
+            ..Default::default()
+        };
+        let error = Simulation2d::new_2d(4, 4, mrt, vec![], vec![])
+            .err()
+            .unwrap();
+        assert_eq!(error.directions, 9);
+        let mut sim = Simulation2d::new_2d(4, 4, Constants::default(), vec![], vec![]).unwrap();
+        let central = Constants {
+            collision: Collision::CentralMoment {
+                bulk: 1.0,
+                higher: 1.0,
+            },
+            ..Default::default()
+        };
+        assert!(sim.set_constants(central).is_err());
+        assert_eq!(sim.constants().collision, Collision::Bgk);
+        let mut sim = Simulation::new(Size3::new(4, 4, 4), mrt, vec![], vec![]);
+        assert!(sim.set_constants(central).is_ok());
+    }
+
+    #[test]
+    fn smagorinsky_slows_relaxation_under_strain() {
+        let directions = D3Q19::DIRECTIONS;
+        let c = 1.0 / Float::sqrt(3.0);
+        let equilibrium = directions.map(|(_, weight)| weight);
+        let rest = smagorinsky(0.17, 1.8, c, &directions, [equilibrium, equilibrium]);
+        assert!(approx_eq(rest, 1.8));
+        // A shear stress in the xy plane.
+        let packets: [Float; 19] = std::array::from_fn(|i| {
+            let (dir, weight) = directions[i];
+            weight + 0.01 * (dir.x * dir.y) as Float
+        });
+        let strained = smagorinsky(0.17, 1.8, c, &directions, [packets, equilibrium]);
+        assert!(strained < 1.8 && strained > 1.0);
+    }
+
+    #[test]
+    fn guo_forcing_adds_momentum() {
+        let directions = D3Q19::DIRECTIONS;
+        let force = Vec3::new(0.001, -0.002, 0.0005);
+        let source = guo_forcing(
+            1.0 / Float::sqrt(3.0),
+            &directions,
+            Vec3::new(0.05, 0.02, -0.01),
+            force,
+        );
+        let mass: Float = source.iter().sum();
+        let momentum: Vec3 = source
+            .iter()
+            .zip(directions)
+            .map(|(value, (dir, _))| *value * Vec3::from(dir))
+            .sum();
+        assert!(approx_eq(mass, 0.0));
+        assert!(approx_eq((momentum - force).dot(momentum - force), 0.0));
+    }
+
+    #[test]
+    fn every_collision_gains_the_force() {
+        let directions = D3Q19::DIRECTIONS;
+        let c = 1.0 / Float::sqrt(3.0);
+        let force = Vec3::new(1e-4, -5e-5, 0.0);
+        let packets: [Float; 19] =
+            std::array::from_fn(|i| directions[i].1 * (1.0 + 0.3 * (i % 5) as Float));
+        let (density, momentum) = moments(&directions, &packets);
+        let velocity = fluid_velocity(density, momentum, force);
+        let equilibrium =
+            directions.map(|(dir, weight)| equilibrium(weight, density, velocity, dir, c));
+        let source = guo_forcing(c, &directions, velocity, force);
+        for collision in [
+            Collision::Bgk,
+            Collision::Mrt(MrtRates::default()),
+            Collision::Trt { magic: 3.0 / 16.0 },
+            Collision::CentralMoment {
+                bulk: 1.0,
+                higher: 1.0,
+            },
+            Collision::Regularized,
+            Collision::Entropic,
+        ] {
+            let collider = Collider::new::<D3Q19>(collision, c);
+            let relaxed = collider.collide(packets, equilibrium, velocity, Some(source), 1.2);
+            let (new_density, new_momentum) = moments(&directions, &relaxed);
+            let gain = new_momentum - momentum;
+            assert!(approx_eq(new_density, density), "{collision:?}");
+            assert!(
+                (gain - force).dot(gain - force).sqrt() < 1e-6,
+                "{collision:?} gains {gain}"
+            );
+        }
+    }
+
+    #[test]
+    fn regularize_keeps_only_stress() {
+        let directions = D3Q19::DIRECTIONS;
+        let equilibrium = directions.map(|(_, weight)| weight);
+        // A shear stress plus noise that carries no stress, mass or momentum.
+        let mrt = Mrt::new(&directions, MrtRates::default());
+        let shear: [Float; 19] = std::array::from_fn(|i| 0.001 * mrt.basis[13][i]);
+        let packets: [Float; 19] =
+            std::array::from_fn(|i| equilibrium[i] + shear[i] + 0.0005 * mrt.basis[16][i]);
+        let off = regularize(&directions, 1.0 / 3.0, packets, equilibrium);
+        for (off, shear) in off.iter().zip(shear) {
+            assert!(approx_eq(*off, shear));
+        }
+    }
+
+    #[test]
+    fn entropic_stretch_keeps_entropy() {
+        let directions = D3Q19::DIRECTIONS;
+        let equilibrium = directions.map(|(_, weight)| weight);
+        let packets: [Float; 19] = std::array::from_fn(|i| {
+            let (dir, weight) = directions[i];
+            weight * (1.0 + 0.2 * (dir.x * dir.y) as Float)
+        });
+        let alpha = entropic_stretch(&directions, packets, equilibrium);
+        let moved = std::array::from_fn(|i| packets[i] + alpha * (equilibrium[i] - packets[i]));
+        assert!((alpha - 2.0).abs() < 0.1);
+        assert!(approx_eq(
+            entropy(&directions, moved),
+            entropy(&directions, packets)
+        ));
+    }
+}
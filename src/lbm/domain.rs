@@ -0,0 +1,440 @@
+use crate::{
+    lbm::{equilibrium, moments, Lattice, Simulation, VelocitySet},
+    math::{Bound3, Float, Int3, Size3, Vec3},
+};
+
+/// What happens to packets at one face of the domain.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FaceCondition {
+    /// Packets leaving through this face come back through the opposite one.
+    #[default]
+    Periodic,
+    /// Fluid enters at a fixed velocity (Zou–He).
+    ///
+    /// This fixes the flow but not the pressure, so some other face must be
+    /// [`FaceCondition::Pressure`] or the density drifts without bound.
+    Velocity(Vec3),
+    /// The density is held fixed, with the velocity and the
+    /// non-equilibrium part of the packets taken from the next cell in
+    /// (non-equilibrium extrapolation).
+    ///
+    /// Zou–He at a pressure face feeds a mode that flips sign every cell and
+    /// every step, which grows wherever the outflow is not uniform.
+    Pressure(Float),
+    /// Incoming packets are copied from the next cell in, so that the flow
+    /// leaves without a gradient across the face.
+    ///
+    /// Nothing holds the density here, so pair it with a
+    /// [`FaceCondition::Pressure`] face rather than only with
+    /// [`FaceCondition::Velocity`] ones.
+    Outflow,
+    /// A no-slip wall halfway past the last cell.
+    Wall,
+}
+
+/// The condition at each face of the domain, which is a [`FaceCondition`]
+/// for the fluid or a [`ScalarCondition`](super::ScalarCondition) for a
+/// scalar.
+///
+/// Opposite faces should either both be periodic or both not be. Where a
+/// wall or a face without flux meets another non-periodic face, it takes the
+/// cells along the edge; elsewhere the face listed later sets the packets
+/// that both faces would.
+#[derive(Clone, Copy, Debug)]
+pub struct DomainBoundaries<C = FaceCondition> {
+    pub x_min: C,
+    pub x_max: C,
+    pub y_min: C,
+    pub y_max: C,
+    pub z_min: C,
+    pub z_max: C,
+}
+
+/// Every face is periodic.
+impl<C: Default> Default for DomainBoundaries<C> {
+    fn default() -> Self {
+        Self {
+            x_min: C::default(),
+            x_max: C::default(),
+            y_min: C::default(),
+            y_max: C::default(),
+            z_min: C::default(),
+            z_max: C::default(),
+        }
+    }
+}
+
+impl DomainBoundaries {
+    /// Steady inflow at the low x face, leaving through the high x face at
+    /// a density of 1, which holds the pressure level in place.
+    pub fn wind_tunnel(velocity: Vec3) -> Self {
+        Self {
+            x_min: FaceCondition::Velocity(velocity),
+            x_max: FaceCondition::Pressure(1.0),
+            ..Default::default()
+        }
+    }
+}
+
+impl<C: Condition> DomainBoundaries<C> {
+    /// The point at `loc`, wrapped around the periodic faces, or `None` when
+    /// it is past a face that is not periodic.
+    pub(super) fn wrap(&self, loc: Int3, size: Size3) -> Option<Bound3> {
+        let axes = [
+            (loc.x, size.x, self.x_min, self.x_max),
+            (loc.y, size.y, self.y_min, self.y_max),
+            (loc.z, size.z, self.z_min, self.z_max),
+        ];
+        for (coord, len, min, max) in axes {
+            let face = match coord {
+                coord if coord < 0 => min,
+                coord if coord >= len as i32 => max,
+                _ => continue,
+            };
+            if face.kind() != FaceKind::Periodic {
+                return None;
+            }
+        }
+        Some(loc.wrap(size))
+    }
+
+    /// Each face's condition with the normal pointing into the domain.
+    pub(super) fn faces(&self) -> [(Int3, C); 6] {
+        [
+            (Int3::new(1, 0, 0), self.x_min),
+            (Int3::new(-1, 0, 0), self.x_max),
+            (Int3::new(0, 1, 0), self.y_min),
+            (Int3::new(0, -1, 0), self.y_max),
+            (Int3::new(0, 0, 1), self.z_min),
+            (Int3::new(0, 0, -1), self.z_max),
+        ]
+    }
+}
+
+/// How the packets on a face are set, which is what the conditions of the
+/// fluid and the scalars have in common.
+#[derive(Clone, Copy, PartialEq)]
+pub enum FaceKind {
+    Periodic,
+    /// The packets that left through the face are sent back, including
+    /// through the edges it shares with other faces.
+    Reflect,
+    /// Incoming packets are copied from the next cell in.
+    Outflow,
+    /// Set by the solver from the condition.
+    Set,
+}
+
+/// A condition on the faces of the domain.
+pub trait Condition: Copy {
+    fn kind(self) -> FaceKind;
+}
+
+impl Condition for FaceCondition {
+    fn kind(self) -> FaceKind {
+        match self {
+            FaceCondition::Periodic => FaceKind::Periodic,
+            FaceCondition::Wall => FaceKind::Reflect,
+            FaceCondition::Outflow => FaceKind::Outflow,
+            FaceCondition::Velocity(_) | FaceCondition::Pressure(_) => FaceKind::Set,
+        }
+    }
+}
+
+/// The cells on the face with the given inward normal.
+pub(super) fn face_cells(size: Size3, normal: Int3) -> impl Iterator<Item = Bound3> {
+    let range = |n: i32, size: usize| match n {
+        1 => 0..1,
+        -1 => size - 1..size,
+        _ => 0..size,
+    };
+    let (xs, ys, zs) = (
+        range(normal.x, size.x),
+        range(normal.y, size.y),
+        range(normal.z, size.z),
+    );
+    xs.flat_map(move |x| ys.clone().map(move |y| (x, y)))
+        .flat_map(move |(x, y)| zs.clone().map(move |z| size.bound(x, y, z).unwrap()))
+}
+
+/// Overwrite the packets that streamed into `distributions` from outside
+/// each non-periodic face.
+///
+/// `set` fills in the faces of [`FaceKind::Set`], given the condition, the
+/// point and inward normal of the face, which packets are incoming, the
+/// packets at the point and those of the next cell in.
+pub(super) fn apply_faces<V: VelocitySet<Q>, const Q: usize, C: Condition>(
+    distributions: &mut Lattice<V, Q>,
+    boundaries: &DomainBoundaries<C>,
+    size: Size3,
+    mut set: impl FnMut(C, Bound3, Int3, &[usize], &mut [Float; Q], [Float; Q]),
+) {
+    let directions = V::DIRECTIONS;
+    let opposites = V::OPPOSITES;
+    let faces = boundaries.faces();
+    // Reflected packets left through the face and streamed onto the opposite
+    // one, where they may be overwritten first.
+    let mut reflected: Vec<_> = faces
+        .iter()
+        .map(|&(normal, condition)| match condition.kind() {
+            FaceKind::Reflect => face_cells(size, normal)
+                .map(|loc| distributions.before_stream(loc))
+                .collect(),
+            _ => vec![],
+        })
+        .collect();
+    let outside = |loc: Bound3, dir: Int3| boundaries.wrap(Int3::from(loc) - dir, size).is_none();
+    let reflecting = |loc: Bound3| {
+        faces.iter().any(|&(normal, condition)| {
+            condition.kind() == FaceKind::Reflect && outside(loc, normal)
+        })
+    };
+    for (&(normal, condition), reflected) in faces.iter().zip(&mut reflected) {
+        if condition.kind() == FaceKind::Periodic {
+            continue;
+        }
+        let mut reflected = reflected.iter();
+        let n = Vec3::from(normal);
+        let incoming: Vec<usize> = (0..directions.len())
+            .filter(|&i| Vec3::from(directions[i].0).dot(n) > 0.0)
+            .collect();
+        for loc in face_cells(size, normal) {
+            let mut f = distributions.cell(loc);
+            let inner = distributions.cell((Int3::from(loc) + normal).wrap(size));
+            match condition.kind() {
+                FaceKind::Periodic => unreachable!(),
+                FaceKind::Reflect => {
+                    // Everything that came from outside, including through
+                    // the other faces at an edge.
+                    let reflected = reflected.next().unwrap();
+                    for (i, (dir, _)) in directions.iter().enumerate() {
+                        if outside(loc, *dir) {
+                            f[i] = reflected[opposites[i]];
+                        }
+                    }
+                }
+                _ if reflecting(loc) => continue,
+                FaceKind::Outflow => {
+                    for &i in &incoming {
+                        f[i] = inner[i];
+                    }
+                }
+                FaceKind::Set => set(condition, loc, normal, &incoming, &mut f, inner),
+            }
+            distributions.set_cell(loc, f);
+        }
+    }
+}
+
+impl<V: VelocitySet<Q>, const Q: usize> Simulation<V, Q> {
+    /// Overwrite the packets that streamed in from outside each non-periodic
+    /// face.
+    pub(super) fn apply_domain_boundaries(&mut self) {
+        let c = self.constants.speed_of_sound;
+        apply_faces(
+            &mut self.distributions,
+            &self.boundaries,
+            self.size,
+            |condition, _, normal, incoming, f, inner| match condition {
+                FaceCondition::Pressure(density) => *f = extrapolate::<V, Q>(c, inner, density),
+                FaceCondition::Velocity(velocity) => {
+                    zou_he::<V, Q>(c, f, normal.into(), incoming, velocity)
+                }
+                _ => unreachable!(),
+            },
+        );
+    }
+}
+
+/// The packets of a cell at `density` that otherwise matches the `inner` one:
+/// the same velocity and distance from equilibrium.
+fn extrapolate<V: VelocitySet<Q>, const Q: usize>(
+    c: Float,
+    inner: [Float; Q],
+    density: Float,
+) -> [Float; Q] {
+    let directions = V::DIRECTIONS;
+    let (inner_density, momentum) = moments(&directions, &inner);
+    let velocity = momentum / inner_density;
+    std::array::from_fn(|i| {
+        let (dir, weight) = directions[i];
+        inner[i] - equilibrium(weight, inner_density, velocity, dir, c)
+            + equilibrium(weight, density, velocity, dir, c)
+    })
+}
+
+/// Set the incoming packets `f[incoming]` at a face with inward normal `n`
+/// from the known packets and the velocity.
+fn zou_he<V: VelocitySet<Q>, const Q: usize>(
+    c: Float,
+    f: &mut [Float; Q],
+    n: Vec3,
+    incoming: &[usize],
+    velocity: Vec3,
+) {
+    let directions = V::DIRECTIONS;
+    let opposites = V::OPPOSITES;
+    // The packets along the face and the ones leaving through it are known.
+    let (mut parallel, mut outgoing) = (0.0, 0.0);
+    for (value, (dir, _)) in f.iter().zip(directions) {
+        match Vec3::from(dir).dot(n) {
+            0.0 => parallel += value,
+            normal if normal < 0.0 => outgoing += value,
+            _ => {}
+        }
+    }
+    let density = (parallel + 2.0 * outgoing) / (1.0 - velocity.dot(n));
+    // Bounce back the non-equilibrium part of the opposite packet.
+    let eq = |i: usize| {
+        let (dir, weight) = directions[i];
+        equilibrium(weight, density, velocity, dir, c)
+    };
+    for &i in incoming {
+        let opposite = opposites[i];
+        f[i] = f[opposite] + eq(i) - eq(opposite);
+    }
+    // Then fix the momentum along the face using the diagonal packets,
+    // shared between however many move along each axis.
+    let momentum: Vec3 = f
+        .iter()
+        .zip(directions)
+        .map(|(value, (dir, _))| *value * Vec3::from(dir))
+        .sum();
+    let error = density * velocity - momentum;
+    let tangential = error - error.dot(n) * n;
+    let spread: Vec3 = incoming
+        .iter()
+        .map(|&i| {
+            let dir = Vec3::from(directions[i].0);
+            Vec3::new(dir.x * dir.x, dir.y * dir.y, dir.z * dir.z)
+        })
+        .sum();
+    let share = |error: Float, spread: Float| if spread > 0.0 { error / spread } else { 0.0 };
+    let correction = Vec3::new(
+        share(tangential.x, spread.x),
+        share(tangential.y, spread.y),
+        share(tangential.z, spread.z),
+    );
+    for &i in incoming {
+        f[i] += Vec3::from(directions[i].0).dot(correction);
+    }
+}
+
+#[cfg(test)]
+mod domain_test {
+    use super::{DomainBoundaries, FaceCondition};
+    use crate::{
+        lbm::{moments, Constants, Simulation, VelocitySet, D3Q19},
+        math::{Float, Int3, Size3, Vec3},
+    };
+
+    const INFLOW: Vec3 = Vec3::new(0.05, 0.0, 0.0);
+    const CHANNEL: Size3 = Size3::new(60, 12, 1);
+
+    /// Wind blowing along a channel between two walls.
+    fn channel(steps: usize) -> Simulation {
+        let mut sim = Simulation::new(CHANNEL, Constants::default(), vec![], vec![]);
+        sim.boundaries = DomainBoundaries {
+            y_min: FaceCondition::Wall,
+            y_max: FaceCondition::Wall,
+            ..DomainBoundaries::wind_tunnel(INFLOW)
+        };
+        for _ in 0..steps {
+            sim.step();
+        }
+        sim
+    }
+
+    /// The density and velocity of the packets at a point.
+    fn conditions(sim: &Simulation, x: usize, y: usize) -> (Float, Vec3) {
+        let packets = sim.distributions.cell(CHANNEL.bound(x, y, 0).unwrap());
+        let (density, momentum) = moments(&D3Q19::DIRECTIONS, &packets);
+        (density, momentum / density)
+    }
+
+    #[test]
+    fn inlet_sets_the_velocity_and_outlet_the_density() {
+        let sim = channel(200);
+        // The walls take the cells along the edges.
+        for y in 1..CHANNEL.y - 1 {
+            let (_, velocity) = conditions(&sim, 0, y);
+            assert!(velocity.approx_eq(INFLOW), "{velocity} at y = {y}");
+            let (density, _) = conditions(&sim, CHANNEL.x - 1, y);
+            assert!((density - 1.0).abs() < 1e-5, "{density} at y = {y}");
+        }
+    }
+
+    #[test]
+    fn walled_channel_keeps_its_mass() {
+        let sim = channel(3000);
+        let cells = (CHANNEL.x * CHANNEL.y) as Float;
+        let mass: Float = (0..CHANNEL.x)
+            .flat_map(|x| (0..CHANNEL.y).map(move |y| (x, y)))
+            .map(|(x, y)| conditions(&sim, x, y).0)
+            .sum();
+        assert!((mass / cells - 1.0).abs() < 0.2, "{}", mass / cells);
+        // What comes in goes out.
+        let flux = |x| -> Float {
+            (0..CHANNEL.y)
+                .map(|y| conditions(&sim, x, y))
+                .map(|(density, velocity)| density * velocity.x)
+                .sum()
+        };
+        let (inlet, outlet) = (flux(1), flux(CHANNEL.x - 2));
+        assert!(
+            (inlet - outlet).abs() < 0.02 * inlet,
+            "{inlet} in, {outlet} out"
+        );
+    }
+
+    #[test]
+    fn outflow_leaves_without_a_gradient() {
+        let mut sim = Simulation::new(CHANNEL, Constants::default(), vec![], vec![]);
+        sim.boundaries = DomainBoundaries {
+            x_min: FaceCondition::Pressure(1.01),
+            x_max: FaceCondition::Outflow,
+            ..Default::default()
+        };
+        for _ in 0..500 {
+            sim.step();
+        }
+        // The fluid drains out at the speed it reaches the face with.
+        let (_, inner) = conditions(&sim, CHANNEL.x - 2, 0);
+        let (_, face) = conditions(&sim, CHANNEL.x - 1, 0);
+        assert!(face.x > 0.0);
+        assert!(
+            (face.x - inner.x).abs() < 0.01 * inner.x,
+            "{face} after {inner}"
+        );
+    }
+
+    #[test]
+    fn walls_do_not_slip() {
+        let sim = channel(3000);
+        // The flow is parabolic across the channel away from the ends,
+        // reaching zero halfway past the last cells.
+        let x = CHANNEL.x * 3 / 4;
+        let speed = |y| conditions(&sim, x, y).1.x;
+        let centre = (speed(CHANNEL.y / 2 - 1) + speed(CHANNEL.y / 2)) / 2.0;
+        for (first, second) in [(0, 1), (CHANNEL.y - 1, CHANNEL.y - 2)] {
+            let at_wall = speed(first) - (speed(second) - speed(first)) / 2.0;
+            assert!(at_wall.abs() < 0.05 * centre, "{at_wall} against {centre}");
+        }
+    }
+
+    #[test]
+    fn wrap_stops_at_faces_that_are_not_periodic() {
+        let size = Size3::new(4, 3, 2);
+        let boundaries = DomainBoundaries {
+            y_max: FaceCondition::Wall,
+            ..DomainBoundaries::wind_tunnel(Vec3::new(0.1, 0.0, 0.0))
+        };
+        let wrap = |x, y, z| boundaries.wrap(Int3::new(x, y, z), size);
+        assert_eq!(wrap(1, 2, 1), size.bound(1, 2, 1).ok());
+        assert_eq!(wrap(-1, 0, 0), None);
+        assert_eq!(wrap(4, 0, 0), None);
+        assert_eq!(wrap(0, 3, 0), None);
+        // The y min and z faces are still periodic.
+        assert_eq!(wrap(0, -1, 2), size.bound(0, 2, 0).ok());
+    }
+}
@@ -1,222 +1,65 @@
 use crate::{
-    lbm::{Lattice, PacketDistribution},
-    math::Int3,
+    lbm::{Lattice, PacketDistribution, VelocitySet},
+    math::{Bound3, Int3},
     Float,
 };
 
-enum LatticeIndex {
-    Q0,
-    Q1(usize),
-    Q2(usize),
-}
+impl<V: VelocitySet<Q>, const Q: usize> Lattice<V, Q> {
+    /// The direction and weight of each distribution, in the order of
+    /// [`Lattice::iter`].
+    pub fn directions() -> [(Int3, Float); Q] {
+        V::DIRECTIONS
+    }
+
+    /// The index of the opposite direction for each of [`Lattice::directions`].
+    pub fn opposites() -> [usize; Q] {
+        V::OPPOSITES
+    }
 
-impl LatticeIndex {
-    // ~100% speedup over not inlined.
-    #[inline(always)]
-    const fn direction(&self) -> Int3 {
-        const fn sign(v: usize) -> i32 {
-            match v.is_multiple_of(2) {
-                true => 1,
-                false => -1,
-            }
+    /// All packets at one point, in the order of [`Lattice::iter`].
+    pub fn cell(&self, loc: Bound3) -> [Float; Q] {
+        let mut values = [0.0; Q];
+        for (value, (dist, _, _)) in values.iter_mut().zip(self.iter()) {
+            *value = *dist.get(loc);
         }
-        let (x, y, z) = match self {
-            LatticeIndex::Q0 => (0, 0, 0),
-            LatticeIndex::Q1(i) => {
-                let i = *i;
-                let a = sign(i);
-                match i {
-                    0..2 => (a, 0, 0),
-                    2..4 => (0, a, 0),
-                    4..6 => (0, 0, a),
-                    _ => unreachable!(),
-                }
-            }
-            LatticeIndex::Q2(i) => {
-                let i = *i;
-                let a = sign(i);
-                let b = sign(i / 2);
-                match i {
-                    0..4 => (a, b, 0),
-                    4..8 => (a, 0, b),
-                    8..12 => (0, a, b),
-                    _ => unreachable!(),
-                }
-            }
-        };
-        Int3::new(x, y, z)
+        values
     }
-    // https://en.wikipedia.org/wiki/Lattice_Boltzmann_methods#Mathematical_equations_for_simulations
-    const fn weight(&self) -> Float {
-        match self {
-            LatticeIndex::Q0 => 1.0 / 3.0,
-            LatticeIndex::Q1(_) => 1.0 / 18.0,
-            LatticeIndex::Q2(_) => 1.0 / 36.0,
+
+    pub fn set_cell(&mut self, loc: Bound3, values: [Float; Q]) {
+        for (value, (dist, _, _)) in values.iter().zip(self.iter_mut()) {
+            *dist.get_mut(loc) = *value;
         }
     }
-}
 
-impl<const X: usize, const Y: usize, const Z: usize> Lattice<X, Y, Z> {
-    pub fn iter_mut(
-        &mut self,
-    ) -> impl Iterator<Item = (&mut PacketDistribution<X, Y, Z>, Int3, Float)> {
-        std::iter::once((
-            self.q0.as_mut(),
-            LatticeIndex::Q0.direction(),
-            LatticeIndex::Q0.weight(),
-        ))
-        .chain(self.q1.iter_mut().enumerate().map(|(i, d)| {
-            (
-                d,
-                LatticeIndex::Q1(i).direction(),
-                LatticeIndex::Q1(i).weight(),
-            )
-        }))
-        .chain(self.q2.iter_mut().enumerate().map(|(i, d)| {
-            (
-                d,
-                LatticeIndex::Q2(i).direction(),
-                LatticeIndex::Q2(i).weight(),
-            )
-        }))
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&mut PacketDistribution, Int3, Float)> {
+        self.distributions
+            .iter_mut()
+            .zip(V::DIRECTIONS)
+            .map(|(dist, (direction, weight))| (dist, direction, weight))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&PacketDistribution, Int3, Float)> {
+        self.distributions
+            .iter()
+            .zip(V::DIRECTIONS)
+            .map(|(dist, (direction, weight))| (dist, direction, weight))
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = (&PacketDistribution<X, Y, Z>, Int3, Float)> {
-        std::iter::once((
-            self.q0.as_ref(),
-            LatticeIndex::Q0.direction(),
-            LatticeIndex::Q0.weight(),
-        ))
-        .chain(self.q1.iter().enumerate().map(|(i, d)| {
-            (
-                d,
-                LatticeIndex::Q1(i).direction(),
-                LatticeIndex::Q1(i).weight(),
-            )
-        }))
-        .chain(self.q2.iter().enumerate().map(|(i, d)| {
-            (
-                d,
-                LatticeIndex::Q2(i).direction(),
-                LatticeIndex::Q2(i).weight(),
-            )
-        }))
+    /// The first direction of each pair returned by [`Lattice::iter_pairs`].
+    pub fn pair_directions() -> Vec<Int3> {
+        V::pairs()
+            .into_iter()
+            .map(|[first, _]| V::DIRECTIONS[first].0)
+            .collect()
     }
 
-    // TODO: this could be way cleaner ... probably
-    pub fn iter_pairs(&mut self) -> [[(&mut PacketDistribution<X, Y, Z>, Int3, Float); 2]; 9] {
-        let [q1_0, q1_1, q1_2, q1_3, q1_4, q1_5] = self.q1.each_mut();
-        let [q2_0, q2_1, q2_2, q2_3, q2_4, q2_5, q2_6, q2_7, q2_8, q2_9, q2_10, q2_11] =
-            self.q2.each_mut();
-        [
-            [
-                (
-                    q1_0,
-                    LatticeIndex::Q1(0).direction(),
-                    LatticeIndex::Q1(0).weight(),
-                ),
-                (
-                    q1_1,
-                    LatticeIndex::Q1(1).direction(),
-                    LatticeIndex::Q1(1).weight(),
-                ),
-            ],
-            [
-                (
-                    q1_2,
-                    LatticeIndex::Q1(2).direction(),
-                    LatticeIndex::Q1(2).weight(),
-                ),
-                (
-                    q1_3,
-                    LatticeIndex::Q1(3).direction(),
-                    LatticeIndex::Q1(3).weight(),
-                ),
-            ],
-            [
-                (
-                    q1_4,
-                    LatticeIndex::Q1(4).direction(),
-                    LatticeIndex::Q1(4).weight(),
-                ),
-                (
-                    q1_5,
-                    LatticeIndex::Q1(5).direction(),
-                    LatticeIndex::Q1(5).weight(),
-                ),
-            ],
-            [
-                (
-                    q2_0,
-                    LatticeIndex::Q2(0).direction(),
-                    LatticeIndex::Q2(0).weight(),
-                ),
-                (
-                    q2_3,
-                    LatticeIndex::Q2(3).direction(),
-                    LatticeIndex::Q2(3).weight(),
-                ),
-            ],
-            [
-                (
-                    q2_1,
-                    LatticeIndex::Q2(1).direction(),
-                    LatticeIndex::Q2(1).weight(),
-                ),
-                (
-                    q2_2,
-                    LatticeIndex::Q2(2).direction(),
-                    LatticeIndex::Q2(2).weight(),
-                ),
-            ],
-            [
-                (
-                    q2_4,
-                    LatticeIndex::Q2(4).direction(),
-                    LatticeIndex::Q2(4).weight(),
-                ),
-                (
-                    q2_7,
-                    LatticeIndex::Q2(7).direction(),
-                    LatticeIndex::Q2(7).weight(),
-                ),
-            ],
-            [
-                (
-                    q2_5,
-                    LatticeIndex::Q2(5).direction(),
-                    LatticeIndex::Q2(5).weight(),
-                ),
-                (
-                    q2_6,
-                    LatticeIndex::Q2(6).direction(),
-                    LatticeIndex::Q2(6).weight(),
-                ),
-            ],
-            [
-                (
-                    q2_8,
-                    LatticeIndex::Q2(8).direction(),
-                    LatticeIndex::Q2(8).weight(),
-                ),
-                (
-                    q2_11,
-                    LatticeIndex::Q2(11).direction(),
-                    LatticeIndex::Q2(11).weight(),
-                ),
-            ],
-            [
-                (
-                    q2_9,
-                    LatticeIndex::Q2(9).direction(),
-                    LatticeIndex::Q2(9).weight(),
-                ),
-                (
-                    q2_10,
-                    LatticeIndex::Q2(10).direction(),
-                    LatticeIndex::Q2(10).weight(),
-                ),
-            ],
-        ]
+    /// Each pair of opposite distributions, in the order of
+    /// [`VelocitySet::pairs`].
+    pub fn iter_pairs(&mut self) -> Vec<[(&mut PacketDistribution, Int3, Float); 2]> {
+        let mut distributions: Vec<_> = self.iter_mut().map(Some).collect();
+        V::pairs()
+            .into_iter()
+            .map(|pair| pair.map(|i| distributions[i].take().unwrap()))
+            .collect()
     }
 }
@@ -0,0 +1,402 @@
+use crate::{
+    lbm::{
+        domain::{apply_faces, Condition, FaceKind},
+        map_in_parallel, CutLink, DomainBoundaries, Field, Lattice, Simulation, VelocitySet,
+        Voxels, D3Q7,
+    },
+    math::{Bound3, Float, Int3, Size3, Vec3},
+};
+
+/// The square of the speed of sound on the [`D3Q7`] lattice.
+const C2: Float = 1.0 / 4.0;
+
+/// What happens to a [`Scalar`] at one face of the domain.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScalarCondition {
+    /// What leaves through this face comes back through the opposite one.
+    #[default]
+    Periodic,
+    /// The scalar is held at this value on the face, such as clean air
+    /// blowing in.
+    Fixed(Float),
+    /// Nothing crosses the face.
+    ZeroFlux,
+    /// Incoming packets are copied from the next cell in, so that the scalar
+    /// leaves with the flow.
+    Outflow,
+}
+
+impl Condition for ScalarCondition {
+    fn kind(self) -> FaceKind {
+        match self {
+            ScalarCondition::Periodic => FaceKind::Periodic,
+            ScalarCondition::ZeroFlux => FaceKind::Reflect,
+            ScalarCondition::Outflow => FaceKind::Outflow,
+            ScalarCondition::Fixed(_) => FaceKind::Set,
+        }
+    }
+}
+
+/// A quantity that the fluid carries and spreads without being changed by
+/// it, such as smoke, scent or pollen.
+///
+/// It streams on its own lattice of [`D3Q7`] packets, with the velocity the
+/// fluid collided with in each step of the [`Simulation`] it is in.
+pub struct Scalar {
+    /// How quickly the scalar spreads through still fluid, in lattice units.
+    ///
+    /// This must be positive, and is most accurate well below one.
+    pub diffusivity: Float,
+    /// The amount of the scalar at each point.
+    pub concentration: Field<Float>,
+    /// The amount added at each point every step, which is negative where the
+    /// scalar is taken away.
+    pub source: Field<Float>,
+    pub boundaries: DomainBoundaries<ScalarCondition>,
+    /// The value held on the surface of each mesh, in the order of
+    /// [`Simulation::meshes`]. Nothing crosses a mesh without one.
+    pub mesh_values: Vec<Option<Float>>,
+    distributions: Lattice<D3Q7, 7>,
+}
+
+impl Scalar {
+    /// None of the scalar on a lattice of `size`, with periodic faces.
+    pub fn new(size: Size3, diffusivity: Float) -> Self {
+        let mut scalar = Self {
+            diffusivity,
+            concentration: Field::new(size),
+            source: Field::new(size),
+            boundaries: DomainBoundaries::default(),
+            mesh_values: vec![],
+            distributions: Lattice::new(size),
+        };
+        scalar.initialize(|_| 0.0);
+        scalar
+    }
+
+    /// Set the concentration at every point, with none of it moving.
+    pub fn initialize(&mut self, value: impl Fn(Bound3) -> Float) {
+        let size = self.concentration.size();
+        for (dist, _, weight) in self.distributions.iter_mut() {
+            for x in 0..size.x {
+                for y in 0..size.y {
+                    for z in 0..size.z {
+                        let loc = size.bound(x, y, z).unwrap();
+                        *dist.get_mut(loc) = weight * value(loc);
+                    }
+                }
+            }
+        }
+        self.calc_concentration();
+    }
+
+    /// Collide and stream the packets once, carried along by `velocity`.
+    ///
+    /// `cut_links` are the links of each pair of [`D3Q7`] directions that
+    /// cross the meshes in `voxels`.
+    fn step(&mut self, velocity: &Field<Vec3>, voxels: Option<&Voxels>, cut_links: &[&[CutLink]]) {
+        self.collide(velocity, voxels);
+        if let Some(voxels) = voxels {
+            self.update_boundary(voxels, cut_links);
+        }
+        self.distributions.shift(1);
+        self.apply_domain_boundaries(velocity);
+        self.calc_concentration();
+    }
+
+    fn collide(&mut self, velocity: &Field<Vec3>, voxels: Option<&Voxels>) {
+        let directions = D3Q7::DIRECTIONS;
+        // The diffusivity is c²(1/ω - 1/2).
+        let omega = 1.0 / (self.diffusivity / C2 + 0.5);
+        let size = self.concentration.size();
+        let source = &self.source;
+        let planes: Vec<_> = self
+            .distributions
+            .planes()
+            .into_iter()
+            .enumerate()
+            .collect();
+        map_in_parallel(planes, |(x, mut plane)| {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let loc = size.bound(x, y, z).unwrap();
+                    // Solids hold none of the scalar.
+                    if voxels.is_some_and(|voxels| voxels.is_solid(loc)) {
+                        plane.set_cell(y, z, [0.0; 7]);
+                        continue;
+                    }
+                    let packets = plane.cell(y, z);
+                    let concentration: Float = packets.iter().sum();
+                    let (velocity, source) = (*velocity.get(loc), *source.get(loc));
+                    let relaxed = std::array::from_fn(|i| {
+                        let (direction, weight) = directions[i];
+                        // The equilibrium only needs to carry the scalar, so
+                        // it stops at first order in the velocity.
+                        let flow = velocity.dot(direction.into());
+                        let equilibrium = weight * concentration * (1.0 + flow / C2);
+                        packets[i] + omega * (equilibrium - packets[i]) + weight * source
+                    });
+                    plane.set_cell(y, z, relaxed);
+                }
+            }
+        });
+    }
+
+    /// Bounce back the packets crossing each mesh, negated around twice the
+    /// mesh's value where it holds one (anti-bounce-back).
+    fn update_boundary(&mut self, voxels: &Voxels, cut_links: &[&[CutLink]]) {
+        let size = self.concentration.size();
+        let mesh_values = &self.mesh_values;
+        let pairs: Vec<_> = self
+            .distributions
+            .iter_pairs()
+            .into_iter()
+            .zip(cut_links)
+            .collect();
+        map_in_parallel(pairs, |([(dist1, dir1, weight), (dist2, _, _)], links)| {
+            // As for the fluid, every packet of a pair of directions is
+            // reflected before any of them are overwritten.
+            let updates: Vec<_> = links
+                .iter()
+                .map(|link| {
+                    let s = link.start;
+                    let d = (Int3::from(s) + dir1).wrap(size);
+                    let reflect = |packet: Float| match mesh_values.get(link.mesh) {
+                        Some(Some(value)) => 2.0 * weight * value - packet,
+                        _ => packet,
+                    };
+                    let to_s = (!voxels.is_solid(s)).then(|| reflect(*dist1.get(s)));
+                    let to_d = (!voxels.is_solid(d)).then(|| reflect(*dist2.get(d)));
+                    (s, to_d, d, to_s)
+                })
+                .collect();
+            for (s, to_d, d, to_s) in updates {
+                if let Some(to_d) = to_d {
+                    *dist1.get_mut(s) = to_d;
+                }
+                if let Some(to_s) = to_s {
+                    *dist2.get_mut(d) = to_s;
+                }
+            }
+        });
+    }
+
+    /// Overwrite the packets that streamed in from outside each non-periodic
+    /// face.
+    fn apply_domain_boundaries(&mut self, velocity: &Field<Vec3>) {
+        let directions = D3Q7::DIRECTIONS;
+        let size = self.concentration.size();
+        apply_faces(
+            &mut self.distributions,
+            &self.boundaries,
+            size,
+            |condition, loc, _, incoming, f, _| {
+                let ScalarCondition::Fixed(value) = condition else {
+                    unreachable!()
+                };
+                // The incoming packets are at equilibrium with whatever
+                // concentration brings the point to `value` (Inamuro et al.
+                // 2002).
+                let velocity = *velocity.get(loc);
+                let shape = |i: usize| {
+                    let (direction, weight) = directions[i];
+                    weight * (1.0 + velocity.dot(direction.into()) / C2)
+                };
+                let known: Float = (0..directions.len())
+                    .filter(|i| !incoming.contains(i))
+                    .map(|i| f[i])
+                    .sum();
+                let spread: Float = incoming.iter().map(|&i| shape(i)).sum();
+                for &i in incoming {
+                    f[i] = (value - known) / spread * shape(i);
+                }
+            },
+        );
+    }
+
+    fn calc_concentration(&mut self) {
+        let size = self.concentration.size();
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let loc = size.bound(x, y, z).unwrap();
+                    *self.concentration.get_mut(loc) = self.distributions.cell(loc).iter().sum();
+                }
+            }
+        }
+    }
+}
+
+impl<V: VelocitySet<Q>, const Q: usize> Simulation<V, Q> {
+    /// Carry every scalar and the temperature one step along with the fluid.
+    pub(super) fn step_scalars(&mut self) {
+        if self.scalars.is_empty() && self.thermal.is_none() {
+            return;
+        }
+        let voxels = match self.meshes.is_empty() {
+            true => None,
+            false => Some(
+                &*self
+                    .voxels
+                    .get_or_insert_with(|| Voxels::new::<V, Q>(self.size, &self.meshes)),
+            ),
+        };
+        // The scalars reuse the links that the fluid found along the axes. A
+        // flat lattice has none along z, where nothing can cross a mesh.
+        let pair_directions = Lattice::<V, Q>::pair_directions();
+        let cut_links: Vec<&[CutLink]> = Lattice::<D3Q7, 7>::pair_directions()
+            .iter()
+            .map(|dir| {
+                let pair = pair_directions.iter().position(|other| other == dir);
+                match (voxels, pair) {
+                    (Some(voxels), Some(pair)) => &voxels.cut_links[pair][..],
+                    _ => &[],
+                }
+            })
+            .collect();
+        let temperature = self
+            .thermal
+            .as_mut()
+            .map(|thermal| &mut thermal.temperature);
+        for scalar in self.scalars.iter_mut().chain(temperature) {
+            scalar.step(&self.velocity, voxels, &cut_links);
+        }
+    }
+}
+
+#[cfg(test)]
+mod scalar_test {
+    use super::{Scalar, ScalarCondition};
+    use crate::{
+        lbm::{equilibrium, Constants, Simulation},
+        math::{Float, Size3, Vec3},
+        mesh::Mesh,
+    };
+
+    /// A still fluid along x with a scalar of `diffusivity`, starting at
+    /// `value` at each x.
+    fn line(length: usize, diffusivity: Float, value: impl Fn(usize) -> Float) -> Simulation {
+        let size = Size3::new(length, 1, 1);
+        let mut sim = Simulation::new(size, Constants::default(), vec![], vec![]);
+        let mut scalar = Scalar::new(size, diffusivity);
+        scalar.initialize(|loc| value(loc.x()));
+        sim.scalars.push(scalar);
+        sim
+    }
+
+    /// The total of the scalar and the mean and variance of where it is
+    /// along x.
+    fn spread(sim: &Simulation) -> (Float, Float, Float) {
+        let concentration = &sim.scalars[0].concentration;
+        let values: Vec<_> = (0..sim.size.x)
+            .map(|x| *concentration.get(sim.size.bound(x, 0, 0).unwrap()))
+            .collect();
+        let total: Float = values.iter().sum();
+        let mean = values
+            .iter()
+            .enumerate()
+            .map(|(x, c)| x as Float * c)
+            .sum::<Float>()
+            / total;
+        let variance = values
+            .iter()
+            .enumerate()
+            .map(|(x, c)| (x as Float - mean).powi(2) * c)
+            .sum::<Float>()
+            / total;
+        (total, mean, variance)
+    }
+
+    #[test]
+    fn spreads_at_its_diffusivity() {
+        let mut sim = line(61, 0.1, |x| if x == 30 { 1.0 } else { 0.0 });
+        for _ in 0..100 {
+            sim.step();
+        }
+        let (total, mean, variance) = spread(&sim);
+        assert!((total - 1.0).abs() < 1e-5, "{total}");
+        assert!((mean - 30.0).abs() < 1e-5, "{mean}");
+        // A spike spreads with a variance of 2Dt.
+        assert!((variance - 20.0).abs() < 0.1, "{variance}");
+    }
+
+    #[test]
+    fn moves_with_the_fluid() {
+        let mut sim = line(61, 0.05, |x| if x == 20 { 1.0 } else { 0.0 });
+        let (velocity, c) = (Vec3::new(0.1, 0.0, 0.0), sim.constants.speed_of_sound);
+        sim.initialize(Box::new(move |args| {
+            Some(equilibrium(args.weight, 1.0, velocity, args.dir, c))
+        }));
+        // The scalar starts at rest, so it takes a few steps to catch up.
+        for _ in 0..10 {
+            sim.step();
+        }
+        let (_, start, _) = spread(&sim);
+        for _ in 0..90 {
+            sim.step();
+        }
+        let (total, mean, _) = spread(&sim);
+        assert!((total - 1.0).abs() < 1e-5, "{total}");
+        assert!((mean - start - 9.0).abs() < 1e-3, "{start} {mean}");
+    }
+
+    #[test]
+    fn fixed_faces_give_a_straight_profile() {
+        let mut sim = line(12, 0.5, |_| 0.0);
+        let boundaries = &mut sim.scalars[0].boundaries;
+        boundaries.x_min = ScalarCondition::Fixed(1.0);
+        boundaries.x_max = ScalarCondition::Fixed(0.0);
+        for _ in 0..3000 {
+            sim.step();
+        }
+        for x in 0..12 {
+            let value = *sim.scalars[0]
+                .concentration
+                .get(sim.size.bound(x, 0, 0).unwrap());
+            let expected = 1.0 - x as Float / 11.0;
+            assert!(
+                (value - expected).abs() < 1e-4,
+                "{x}: {value} != {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn meshes_hold_their_value_or_keep_the_scalar_in() {
+        let size = Size3::new(6, 6, 6);
+        let block = Mesh::cuboid(Vec3::new(1.5, 1.5, 1.5), Vec3::new(3.5, 3.5, 3.5));
+        let mut sim = Simulation::new(size, Constants::default(), vec![], vec![block]);
+        let mut held = Scalar::new(size, 0.5);
+        held.mesh_values = vec![Some(1.0)];
+        let mut kept = Scalar::new(size, 0.3);
+        kept.initialize(|loc| loc.x() as Float);
+        sim.scalars = vec![held, kept];
+        let fluid_total = |sim: &Simulation, scalar: usize| {
+            let voxels = sim.voxels.as_ref().unwrap();
+            let mut total = 0.0;
+            for x in 0..size.x {
+                for y in 0..size.y {
+                    for z in 0..size.z {
+                        let loc = size.bound(x, y, z).unwrap();
+                        if !voxels.is_solid(loc) {
+                            total += sim.scalars[scalar].concentration.get(loc);
+                        }
+                    }
+                }
+            }
+            total
+        };
+        sim.step();
+        let start = fluid_total(&sim, 1);
+        for _ in 0..500 {
+            sim.step();
+        }
+        assert!((fluid_total(&sim, 1) - start).abs() < 1e-6 * start);
+        // Far from the block, in the corner, the scalar reaches the block's
+        // value.
+        let corner = *sim.scalars[0]
+            .concentration
+            .get(size.bound(0, 0, 0).unwrap());
+        assert!((corner - 1.0).abs() < 1e-2, "{corner}");
+    }
+}
@@ -0,0 +1,188 @@
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::math::{Float, Int3};
+
+/// Several values that arithmetic acts on at once, one per lane.
+pub(super) trait Lanes:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self>
+{
+    const LANES: usize;
+
+    fn splat(value: Float) -> Self;
+    /// The lanes `value(0)`, `value(1)` and so on.
+    fn gather(value: impl FnMut(usize) -> Float) -> Self;
+    /// Pass every lane to `f` with its index.
+    fn scatter(self, f: impl FnMut(usize, Float));
+    /// The lanes from the start of `values`.
+    fn load(values: &[Float]) -> Self;
+    /// Write the lanes to the start of `values`.
+    fn store(self, values: &mut [Float]);
+}
+
+// The widest lanes available, which are a single `Float` without the `simd`
+// feature.
+#[cfg(all(feature = "simd", not(feature = "f64")))]
+pub(super) type Simd = wide::f32x8;
+#[cfg(all(feature = "simd", feature = "f64"))]
+pub(super) type Simd = wide::f64x4;
+#[cfg(not(feature = "simd"))]
+pub(super) type Simd = Float;
+
+impl Lanes for Float {
+    const LANES: usize = 1;
+
+    #[inline]
+    fn splat(value: Float) -> Self {
+        value
+    }
+    #[inline]
+    fn gather(mut value: impl FnMut(usize) -> Float) -> Self {
+        value(0)
+    }
+    #[inline]
+    fn scatter(self, mut f: impl FnMut(usize, Float)) {
+        f(0, self)
+    }
+    #[inline]
+    fn load(values: &[Float]) -> Self {
+        values[0]
+    }
+    #[inline]
+    fn store(self, values: &mut [Float]) {
+        values[0] = self;
+    }
+}
+
+#[cfg(feature = "simd")]
+impl Lanes for Simd {
+    const LANES: usize = std::mem::size_of::<Simd>() / std::mem::size_of::<Float>();
+
+    #[inline]
+    fn splat(value: Float) -> Self {
+        Simd::splat(value)
+    }
+    #[inline]
+    fn gather(value: impl FnMut(usize) -> Float) -> Self {
+        Simd::from(std::array::from_fn::<Float, { Self::LANES }, _>(value))
+    }
+    #[inline]
+    fn scatter(self, mut f: impl FnMut(usize, Float)) {
+        for (i, value) in self.to_array().into_iter().enumerate() {
+            f(i, value)
+        }
+    }
+    #[inline]
+    fn load(values: &[Float]) -> Self {
+        Simd::from(<[Float; Self::LANES]>::try_from(&values[..Self::LANES]).unwrap())
+    }
+    #[inline]
+    fn store(self, values: &mut [Float]) {
+        values[..Self::LANES].copy_from_slice(&self.to_array());
+    }
+}
+
+/// The component of `vector` along a lattice direction.
+fn along<L: Lanes>(direction: Int3, [x, y, z]: [L; 3]) -> L {
+    let component = |c: i32| L::splat(c as Float);
+    x * component(direction.x) + y * component(direction.y) + z * component(direction.z)
+}
+
+/// BGK collision with Guo forcing for one point per lane, the same as
+/// [`Collision::Bgk`](super::Collision::Bgk) with a body force.
+///
+/// Returns the relaxed packets with the density and velocity of each point.
+pub(super) fn collide_bgk<L: Lanes, const Q: usize>(
+    directions: &[(Int3, Float); Q],
+    [omega, speed_of_sound]: [Float; 2],
+    packets: [L; Q],
+    force: [L; 3],
+) -> ([L; Q], L, [L; 3]) {
+    let zero = L::splat(0.0);
+    let (mut density, mut momentum) = (zero, [zero; 3]);
+    for (packet, (direction, _)) in packets.into_iter().zip(directions) {
+        density = density + packet;
+        momentum = [
+            momentum[0] + packet * L::splat(direction.x as Float),
+            momentum[1] + packet * L::splat(direction.y as Float),
+            momentum[2] + packet * L::splat(direction.z as Float),
+        ];
+    }
+    // Half of the body force acts before the packets are counted.
+    let inverse_density = L::splat(1.0) / density;
+    let velocity: [L; 3] =
+        std::array::from_fn(|i| (momentum[i] + L::splat(0.5) * force[i]) * inverse_density);
+    let c2 = speed_of_sound * speed_of_sound;
+    let (inverse_c2, inverse_c4) = (L::splat(1.0 / c2), L::splat(1.0 / (c2 * c2)));
+    let half = L::splat(0.5);
+    let dot = |[x, y, z]: [L; 3], [a, b, c]: [L; 3]| x * a + y * b + z * c;
+    let rest = L::splat(1.0) - half * dot(velocity, velocity) * inverse_c2;
+    let velocity_force = dot(velocity, force);
+    let keep = L::splat(1.0 - omega);
+    let mut relaxed = packets;
+    for (relaxed, (direction, weight)) in relaxed.iter_mut().zip(directions) {
+        let flow = along(*direction, velocity);
+        let equilibrium = L::splat(omega * weight)
+            * density
+            * (rest + flow * inverse_c2 + half * flow * flow * inverse_c4);
+        let push = along(*direction, force);
+        let source = L::splat((1.0 - omega / 2.0) * weight)
+            * ((push - velocity_force) * inverse_c2 + flow * push * inverse_c4);
+        *relaxed = equilibrium + *relaxed * keep + source;
+    }
+    (relaxed, density, velocity)
+}
+
+#[cfg(test)]
+mod simd_test {
+    use super::{collide_bgk, Lanes, Simd};
+    use crate::{
+        lbm::{fluid_velocity, moments, Constants, Relaxation, VelocitySet, D3Q19},
+        math::{Float, Vec3},
+    };
+
+    #[test]
+    fn lanes_match_scalar_bgk() {
+        let constants = Constants {
+            time_relaxation_constant: 1.7,
+            ..Default::default()
+        };
+        let relaxation = Relaxation::new::<D3Q19>(&constants);
+        let directions = D3Q19::DIRECTIONS;
+        // A different flow and force in each lane.
+        let point = |lane: usize| {
+            let phase = lane as Float;
+            let packets: [Float; 19] =
+                std::array::from_fn(|i| directions[i].1 * (1.0 + 0.1 * (phase + i as Float).sin()));
+            let force = Vec3::new(1e-3 * phase, -2e-3, 1e-3 * (phase * 0.7).cos());
+            (packets, force)
+        };
+        let packets = std::array::from_fn(|i| Simd::gather(|lane| point(lane).0[i]));
+        let force = [
+            Simd::gather(|lane| point(lane).1.x),
+            Simd::gather(|lane| point(lane).1.y),
+            Simd::gather(|lane| point(lane).1.z),
+        ];
+        let rates = [constants.time_relaxation_constant, constants.speed_of_sound];
+        let (relaxed, density, _) = collide_bgk(&directions, rates, packets, force);
+        for lane in 0..Simd::LANES {
+            let (packets, force) = point(lane);
+            let (expected_density, momentum) = moments(&directions, &packets);
+            let velocity = fluid_velocity(expected_density, momentum, force);
+            let (expected, _) = relaxation.relax(packets, expected_density, velocity, force);
+            let lane_of = |lanes: Simd| {
+                let mut value = 0.0;
+                lanes.scatter(|i, v| {
+                    if i == lane {
+                        value = v;
+                    }
+                });
+                value
+            };
+            assert!((lane_of(density) - expected_density).abs() < 1e-6);
+            for (relaxed, expected) in relaxed.into_iter().zip(expected) {
+                let relaxed = lane_of(relaxed);
+                assert!((relaxed - expected).abs() < 1e-6, "{relaxed} != {expected}");
+            }
+        }
+    }
+}
@@ -0,0 +1,471 @@
+use crate::{
+    lbm::{
+        fluid_velocity, map_in_parallel, moments,
+        simd::{collide_bgk, Lanes, Simd},
+        thermal::body_force,
+        Lattice, Relaxation, Simulation, VelocitySet,
+    },
+    math::{Bound3, Float, Int3, Size3, Vec3},
+};
+
+/// The packets stored for every point with the same x, which can be collided
+/// independently of the other planes.
+pub(super) struct Plane<'a, const Q: usize> {
+    size: Size3,
+    offsets: [Int3; Q],
+    /// A y-z slice of each distribution, in the order of [`Lattice::iter`].
+    values: [&'a mut [Float]; Q],
+}
+
+impl<const Q: usize> Plane<'_, Q> {
+    /// Where each distribution stores the packets of row `y`.
+    fn row(&self, y: usize) -> Row<Q> {
+        let size = self.size;
+        Row {
+            starts: self
+                .offsets
+                .map(|offset| ((y + offset.y as usize) % size.y * size.z, offset.z as usize)),
+            len: size.z,
+        }
+    }
+
+    /// Where each distribution stores the packets of a plane that is one
+    /// point deep in z, as a single row along y.
+    fn flat_row(&self) -> Row<Q> {
+        Row {
+            starts: self.offsets.map(|offset| (0, offset.y as usize)),
+            len: self.size.y,
+        }
+    }
+
+    /// All packets at a point of the plane, in the order of [`Lattice::iter`].
+    pub(super) fn cell(&self, y: usize, z: usize) -> [Float; Q] {
+        let row = self.row(y);
+        std::array::from_fn(|i| self.values[i][row.index(i, z)])
+    }
+
+    pub(super) fn set_cell(&mut self, y: usize, z: usize, values: [Float; Q]) {
+        let row = self.row(y);
+        for (i, value) in values.into_iter().enumerate() {
+            self.values[i][row.index(i, z)] = value;
+        }
+    }
+}
+
+/// Where the packets of one row of a [`Plane`] are stored.
+struct Row<const Q: usize> {
+    /// Where the row starts in each distribution, and how far along it the
+    /// row wraps around.
+    starts: [(usize, usize); Q],
+    len: usize,
+}
+
+impl<const Q: usize> Row<Q> {
+    /// The index of the packet for point `z` in distribution `i`.
+    #[inline(always)]
+    fn index(&self, i: usize, z: usize) -> usize {
+        let (start, shift) = self.starts[i];
+        let z = z + shift;
+        start + if z < self.len { z } else { z - self.len }
+    }
+
+    /// The index of the packet for point `z` in distribution `i` if the next
+    /// `count` packets follow it without wrapping around.
+    #[inline(always)]
+    fn contiguous(&self, i: usize, z: usize, count: usize) -> Option<usize> {
+        let (start, shift) = self.starts[i];
+        (z + shift + count <= self.len).then_some(start + z + shift)
+    }
+}
+
+/// A plane of packets with the fields at the same points, which are collided
+/// and streamed together.
+struct FusedPlane<'a, const Q: usize> {
+    packets: Plane<'a, Q>,
+    opposites: [usize; Q],
+    forces: &'a [Vec3],
+    densities: &'a mut [Float],
+    velocities: &'a mut [Vec3],
+    eddy_viscosities: &'a mut [Float],
+}
+
+impl<const Q: usize> FusedPlane<'_, Q> {
+    /// Collide point `z` of row `y` and write each packet back where the
+    /// opposite one was read from.
+    fn collide(&mut self, relaxation: &Relaxation<Q>, row: &Row<Q>, y: usize, z: usize) {
+        let values = &mut self.packets.values;
+        let packets = std::array::from_fn(|i| values[i][row.index(i, z)]);
+        let point = y * self.packets.size.z + z;
+        let force = self.forces[point];
+        let (density, momentum) = moments(&relaxation.directions, &packets);
+        let velocity = fluid_velocity(density, momentum, force);
+        let (relaxed, eddy_viscosity) = relaxation.relax(packets, density, velocity, force);
+        for (i, value) in relaxed.into_iter().enumerate() {
+            let opposite = self.opposites[i];
+            values[opposite][row.index(opposite, z)] = value;
+        }
+        self.densities[point] = density;
+        self.velocities[point] = velocity;
+        self.eddy_viscosities[point] = eddy_viscosity;
+    }
+
+    /// Collide one point per lane from point `z` of row `y` on with plain
+    /// BGK, the same way as [`FusedPlane::collide`].
+    fn collide_bgk<L: Lanes>(
+        &mut self,
+        relaxation: &Relaxation<Q>,
+        row: &Row<Q>,
+        y: usize,
+        z: usize,
+    ) {
+        let values = &mut self.packets.values;
+        let packets = std::array::from_fn(|i| match row.contiguous(i, z, L::LANES) {
+            Some(index) => L::load(&values[i][index..]),
+            None => L::gather(|lane| values[i][row.index(i, z + lane)]),
+        });
+        let point = y * self.packets.size.z + z;
+        let forces = &self.forces[point..point + L::LANES];
+        let force = [
+            L::gather(|lane| forces[lane].x),
+            L::gather(|lane| forces[lane].y),
+            L::gather(|lane| forces[lane].z),
+        ];
+        let constants = relaxation.constants;
+        let rates = [constants.time_relaxation_constant, constants.speed_of_sound];
+        let (relaxed, density, velocity) =
+            collide_bgk(&relaxation.directions, rates, packets, force);
+        for (i, relaxed) in relaxed.into_iter().enumerate() {
+            let opposite = self.opposites[i];
+            match row.contiguous(opposite, z, L::LANES) {
+                Some(index) => relaxed.store(&mut values[opposite][index..]),
+                None => relaxed
+                    .scatter(|lane, value| values[opposite][row.index(opposite, z + lane)] = value),
+            }
+        }
+        let point = point..point + L::LANES;
+        density.scatter(|lane, value| self.densities[point.start + lane] = value);
+        let velocities = &mut self.velocities[point.clone()];
+        velocity[0].scatter(|lane, value| velocities[lane].x = value);
+        velocity[1].scatter(|lane, value| velocities[lane].y = value);
+        velocity[2].scatter(|lane, value| velocities[lane].z = value);
+        self.eddy_viscosities[point].fill(0.0);
+    }
+}
+
+impl<V: VelocitySet<Q>, const Q: usize> Lattice<V, Q> {
+    /// Move every packet `steps` cells along its direction, wrapping around
+    /// the edges. Only the offsets of the distributions change, so this is
+    /// free, and a negative `steps` undoes it.
+    pub(super) fn shift(&mut self, steps: i32) {
+        let size = self.size();
+        for (dist, direction, _) in self.iter_mut() {
+            let step = Int3::new(
+                steps * direction.x,
+                steps * direction.y,
+                steps * direction.z,
+            );
+            dist.offset = Int3::from((dist.offset - step).wrap(size));
+        }
+    }
+
+    /// All packets at one point as they were before the last stream, in the
+    /// order of [`Lattice::iter`].
+    ///
+    /// These are the packets that streamed out of the point, so they are
+    /// only left when nothing has overwritten them since.
+    pub(super) fn before_stream(&self, loc: Bound3) -> [Float; Q] {
+        let size = self.size();
+        let mut values = [0.0; Q];
+        for (value, (dist, direction, _)) in values.iter_mut().zip(self.iter()) {
+            *value = *dist.get((Int3::from(loc) + direction).wrap(size));
+        }
+        values
+    }
+
+    /// Split the packets into the planes of each x, in order.
+    pub(super) fn planes(&mut self) -> Vec<Plane<'_, Q>> {
+        let size = self.size();
+        let mut offsets = [Int3::ZERO; Q];
+        for (offset, (dist, _, _)) in offsets.iter_mut().zip(self.iter()) {
+            *offset = dist.offset;
+        }
+        let mut slices: Vec<Vec<Option<&mut [Float]>>> = self
+            .iter_mut()
+            .map(|(dist, _, _)| dist.values.chunks_mut(size.y * size.z).map(Some).collect())
+            .collect();
+        (0..size.x)
+            .map(|x| Plane {
+                size,
+                offsets,
+                values: std::array::from_fn(|i| {
+                    slices[i][(x + offsets[i].x as usize) % size.x]
+                        .take()
+                        .unwrap()
+                }),
+            })
+            .collect()
+    }
+}
+
+impl<V: VelocitySet<Q>, const Q: usize> Simulation<V, Q> {
+    /// Collide every point and stream the packets in the same pass over the
+    /// lattice, without a second copy of it (the AA pattern of Bailey et al.
+    /// 2009).
+    ///
+    /// Each point reads its packets, relaxes them and writes every one back
+    /// where the packet in the opposite direction was read from. Swapping the
+    /// opposite distributions afterwards leaves each packet where it started
+    /// but in the right distribution, so that shifting the distributions by
+    /// one step streams them. The offsets alternate between zero and one
+    /// step back, so every other pass reads and writes the neighbours.
+    pub(super) fn collide_and_stream(&mut self) {
+        let opposites = V::OPPOSITES;
+        let relaxation = Relaxation::new::<V>(&self.constants);
+        let bgk = relaxation.is_bgk();
+        let size = self.size;
+        let planes: Vec<_> = self
+            .distributions
+            .planes()
+            .into_iter()
+            .zip(body_force(&self.force, &self.thermal).planes())
+            .zip(self.density.planes_mut())
+            .zip(self.velocity.planes_mut())
+            .zip(self.eddy_viscosity.planes_mut())
+            .map(
+                |((((packets, forces), densities), velocities), eddy_viscosities)| FusedPlane {
+                    packets,
+                    opposites,
+                    forces,
+                    densities,
+                    velocities,
+                    eddy_viscosities,
+                },
+            )
+            .collect();
+        // A flat lattice runs each plane as one row, which keeps the lanes
+        // full. The points of the row are then numbered by y alone.
+        let (rows, length) = match size.z {
+            1 => (1, size.y),
+            _ => (size.y, size.z),
+        };
+        map_in_parallel(planes, |mut plane| {
+            for y in 0..rows {
+                let row = match size.z {
+                    1 => plane.packets.flat_row(),
+                    _ => plane.packets.row(y),
+                };
+                let mut z = 0;
+                while z < length {
+                    if !bgk {
+                        plane.collide(&relaxation, &row, y, z);
+                        z += 1;
+                    } else if z + Simd::LANES <= length {
+                        plane.collide_bgk::<Simd>(&relaxation, &row, y, z);
+                        z += Simd::LANES;
+                    } else {
+                        plane.collide_bgk::<Float>(&relaxation, &row, y, z);
+                        z += 1;
+                    }
+                }
+            }
+        });
+        for [(first, _, _), (second, _, _)] in self.distributions.iter_pairs() {
+            std::mem::swap(first, second);
+        }
+        self.distributions.shift(1);
+        self.stale_conditions = true;
+    }
+}
+
+#[cfg(test)]
+mod streaming_test {
+    use crate::{
+        lbm::{
+            Constants, DomainBoundaries, FaceCondition, Lattice, SimStep, Simulation, Thermal,
+            VelocitySet, D2Q9, D3Q15, D3Q19, D3Q27,
+        },
+        math::{Float, Int3, Size3, Vec3},
+        mesh::Mesh,
+    };
+
+    // Long enough in z to fill the widest lanes.
+    const SIZE: Size3 = Size3::new(6, 5, 11);
+
+    #[test]
+    fn shift_wraps_every_direction() {
+        let mut lattice = Lattice::<D3Q27, 27>::new(SIZE);
+        for (dist, _, _) in lattice.iter_mut() {
+            for x in 0..SIZE.x {
+                for y in 0..SIZE.y {
+                    for z in 0..SIZE.z {
+                        let loc = SIZE.bound(x, y, z).unwrap();
+                        *dist.get_mut(loc) = SIZE.index(loc) as Float;
+                    }
+                }
+            }
+        }
+        let source = lattice.clone();
+        lattice.shift(1);
+        for ((target, direction, _), (source, _, _)) in lattice.iter().zip(source.iter()) {
+            for x in 0..SIZE.x as i32 {
+                for y in 0..SIZE.y as i32 {
+                    for z in 0..SIZE.z as i32 {
+                        let loc = Int3::new(x, y, z);
+                        assert_eq!(
+                            target.get((loc + direction).wrap(SIZE)),
+                            source.get(loc.wrap(SIZE)),
+                            "{direction:?} at {loc:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn block() -> Mesh {
+        Mesh::cuboid(Vec3::new(2.5, 2.5, 1.5), Vec3::new(3.5, 3.5, 2.5))
+    }
+
+    fn simulation<V: VelocitySet<Q> + Default, const Q: usize>(
+        size: Size3,
+        smagorinsky: Option<Float>,
+        block: Mesh,
+    ) -> Simulation<V, Q> {
+        let constants = Constants {
+            smagorinsky,
+            ..Default::default()
+        };
+        let mut sim =
+            Simulation::with_velocity_set(V::default(), size, constants, vec![], vec![block])
+                .unwrap();
+        sim.boundaries = DomainBoundaries {
+            y_min: FaceCondition::Wall,
+            y_max: FaceCondition::Wall,
+            ..DomainBoundaries::wind_tunnel(Vec3::new(0.05, 0.0, 0.0))
+        };
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    *sim.force.get_mut(size.bound(x, y, z).unwrap()) = Vec3::new(1e-4, 0.0, 0.0);
+                }
+            }
+        }
+        sim.initialize(Box::new(|args| {
+            let (x, y, z) = args.loc;
+            let phase = (x + 2 * y + 3 * z) as Float + Vec3::from(args.dir).x;
+            Some(args.weight * (1.0 + 0.05 * phase.sin()))
+        }));
+        sim
+    }
+
+    #[test]
+    fn fused_step_matches_small_steps() {
+        // Plain BGK runs several points at once.
+        for smagorinsky in [Some(0.17), None] {
+            compare_fused_and_small_steps(|| simulation::<D3Q19, 19>(SIZE, smagorinsky, block()));
+        }
+        compare_fused_and_small_steps(|| simulation::<D2Q9, 9>(SIZE, None, block()));
+        compare_fused_and_small_steps(|| simulation::<D3Q15, 15>(SIZE, None, block()));
+        compare_fused_and_small_steps(|| simulation::<D3Q27, 27>(SIZE, Some(0.17), block()));
+    }
+
+    #[test]
+    fn flat_step_matches_small_steps() {
+        // Each plane is one row along y, which is long enough for the lanes.
+        let size = Size3::new(6, 11, 1);
+        let square = [[2.5, 2.5], [3.5, 2.5], [3.5, 3.5], [2.5, 3.5]];
+        compare_fused_and_small_steps(|| {
+            simulation::<D2Q9, 9>(size, None, Mesh::polyline(&square, true))
+        });
+    }
+
+    #[test]
+    fn thermal_step_matches_small_steps() {
+        // The buoyancy of each step comes from the temperature before it.
+        let size = Size3::new(6, 11, 1);
+        let square = [[2.5, 2.5], [3.5, 2.5], [3.5, 3.5], [2.5, 3.5]];
+        compare_fused_and_small_steps(|| {
+            let mut sim = simulation::<D2Q9, 9>(size, None, Mesh::polyline(&square, true));
+            let mut thermal = Thermal::new(size, 0.2, 0.5, Vec3::new(0.0, 1e-3, 0.0));
+            thermal
+                .temperature
+                .initialize(|loc| (loc.x() as Float).sin() + loc.y() as Float / 11.0);
+            thermal.temperature.mesh_values = vec![Some(1.0)];
+            sim.thermal = Some(thermal);
+            sim
+        });
+    }
+
+    fn compare_fused_and_small_steps<V: VelocitySet<Q>, const Q: usize>(
+        simulation: impl Fn() -> Simulation<V, Q>,
+    ) {
+        let (mut fused, mut staged) = (simulation(), simulation());
+        let size = fused.size;
+        for i in 0..6 {
+            // Small steps after fused ones have to catch up on the density
+            // and velocity first.
+            if i == 3 {
+                while !matches!(fused.small_step(), SimStep::Collide) {}
+            } else {
+                fused.step();
+            }
+            while !matches!(staged.small_step(), SimStep::Collide) {}
+        }
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    let loc = size.bound(x, y, z).unwrap();
+                    let (fused, staged) = (
+                        fused.distributions.cell(loc),
+                        staged.distributions.cell(loc),
+                    );
+                    for (fused, staged) in fused.into_iter().zip(staged) {
+                        assert!(
+                            (fused - staged).abs() < 1e-5,
+                            "{fused} != {staged} at {loc:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn threads_do_not_change_results() {
+        let run = |threads| {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                let mut sim = simulation::<D3Q19, 19>(SIZE, Some(0.17), block());
+                for i in 0..6 {
+                    if i == 3 {
+                        while !matches!(sim.small_step(), SimStep::Collide) {}
+                    } else {
+                        sim.step();
+                    }
+                }
+                sim
+            })
+        };
+        let (serial, parallel) = (run(1), run(4));
+        for x in 0..SIZE.x {
+            for y in 0..SIZE.y {
+                for z in 0..SIZE.z {
+                    let loc = SIZE.bound(x, y, z).unwrap();
+                    let (serial, parallel) = (
+                        serial.distributions.cell(loc),
+                        parallel.distributions.cell(loc),
+                    );
+                    for (serial, parallel) in serial.into_iter().zip(parallel) {
+                        assert_eq!(serial.to_bits(), parallel.to_bits(), "at {loc:?}");
+                    }
+                }
+            }
+        }
+        let force = |sim: &Simulation| sim.mesh_forces[0].force;
+        assert_eq!(force(&serial), force(&parallel));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    lbm::{Field, Scalar, Simulation, VelocitySet},
+    math::{Float, Size3, Vec3},
+};
+
+/// Heat carried by the fluid, which lifts the fluid where it is warmer than
+/// `reference` and sinks it where it is cooler (the Boussinesq
+/// approximation).
+///
+/// The temperature is a [`Scalar`]. Faces of its `boundaries` with
+/// [`ScalarCondition::Fixed`](super::ScalarCondition::Fixed) are held at that
+/// temperature and faces with
+/// [`ScalarCondition::ZeroFlux`](super::ScalarCondition::ZeroFlux) are
+/// adiabatic. Its `mesh_values` do the same for meshes, which are adiabatic
+/// without one.
+pub struct Thermal {
+    pub temperature: Scalar,
+    /// The temperature at which the fluid neither rises nor sinks.
+    pub reference: Float,
+    /// The acceleration of the fluid for each degree above `reference`,
+    /// which is gravity times the thermal expansion coefficient, pointing up.
+    pub buoyancy: Vec3,
+    /// [`Simulation::force`] with the buoyancy added, which the fluid
+    /// collides with instead.
+    force: Field<Vec3>,
+}
+
+impl Thermal {
+    /// Fluid of `size` at the `reference` temperature everywhere, which heat
+    /// spreads through at `diffusivity`.
+    pub fn new(size: Size3, diffusivity: Float, reference: Float, buoyancy: Vec3) -> Self {
+        let mut temperature = Scalar::new(size, diffusivity);
+        temperature.initialize(|_| reference);
+        Self {
+            temperature,
+            reference,
+            buoyancy,
+            force: Field::new(size),
+        }
+    }
+}
+
+/// The body force that the fluid collides with.
+pub(super) fn body_force<'a>(
+    force: &'a Field<Vec3>,
+    thermal: &'a Option<Thermal>,
+) -> &'a Field<Vec3> {
+    match thermal {
+        Some(thermal) => &thermal.force,
+        None => force,
+    }
+}
+
+impl<V: VelocitySet<Q>, const Q: usize> Simulation<V, Q> {
+    /// Add the buoyancy of the current temperature to the body force.
+    pub(super) fn apply_buoyancy(&mut self) {
+        let Some(thermal) = &mut self.thermal else {
+            return;
+        };
+        let (reference, buoyancy) = (thermal.reference, thermal.buoyancy);
+        let temperatures = &thermal.temperature.concentration.values;
+        for ((total, force), temperature) in thermal
+            .force
+            .values
+            .iter_mut()
+            .zip(&self.force.values)
+            .zip(temperatures)
+        {
+            *total = *force + (temperature - reference) * buoyancy;
+        }
+        // The velocity includes half of the force, so it has changed too.
+        self.stale_conditions = true;
+    }
+}
+
+#[cfg(test)]
+mod thermal_test {
+    use super::Thermal;
+    use crate::{
+        lbm::{Constants, DomainBoundaries, FaceCondition, ScalarCondition, Simulation2d},
+        math::Vec3,
+        mesh::Mesh,
+    };
+
+    const UP: Vec3 = Vec3::new(0.0, 1e-3, 0.0);
+
+    fn velocity(sim: &Simulation2d, x: usize, y: usize) -> Vec3 {
+        *sim.velocity.get(sim.size.bound(x, y, 0).unwrap())
+    }
+
+    #[test]
+    fn warm_fluid_rises_and_cool_fluid_sinks() {
+        let mut sim = Simulation2d::new_2d(8, 8, Constants::default(), vec![], vec![]).unwrap();
+        let mut thermal = Thermal::new(sim.size, 0.1, 0.0, UP);
+        thermal
+            .temperature
+            .initialize(|loc| if loc.x() < 4 { 1.0 } else { -1.0 });
+        sim.thermal = Some(thermal);
+        for _ in 0..5 {
+            sim.step();
+        }
+        assert!(velocity(&sim, 1, 4).y > 0.0);
+        assert!(velocity(&sim, 5, 4).y < 0.0);
+    }
+
+    #[test]
+    fn hot_mesh_sets_off_a_plume() {
+        let square = [[4.5, 2.5], [7.5, 2.5], [7.5, 5.5], [4.5, 5.5]];
+        let meshes = vec![Mesh::polyline(&square, true)];
+        let mut sim = Simulation2d::new_2d(12, 16, Constants::default(), vec![], meshes).unwrap();
+        sim.boundaries = DomainBoundaries {
+            y_min: FaceCondition::Wall,
+            y_max: FaceCondition::Wall,
+            ..Default::default()
+        };
+        let mut thermal = Thermal::new(sim.size, 0.1, 0.0, UP);
+        thermal.temperature.boundaries = DomainBoundaries {
+            y_min: ScalarCondition::ZeroFlux,
+            y_max: ScalarCondition::ZeroFlux,
+            ..Default::default()
+        };
+        thermal.temperature.mesh_values = vec![Some(1.0)];
+        sim.thermal = Some(thermal);
+        for _ in 0..200 {
+            sim.step();
+        }
+        let above = sim.size.bound(6, 8, 0).unwrap();
+        let temperature = *sim
+            .thermal
+            .as_ref()
+            .unwrap()
+            .temperature
+            .concentration
+            .get(above);
+        assert!(temperature > 0.1, "{temperature}");
+        assert!(velocity(&sim, 6, 8).y > 0.0);
+        // The fluid comes back down at the sides.
+        assert!(velocity(&sim, 0, 8).y < 0.0);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::math::{Float, Int3};
+
+/// The directions that packets stream in, with the weight of each in the
+/// equilibrium at rest.
+///
+/// `Q` is the number of directions, which sizes the packets at each point.
+pub trait VelocitySet<const Q: usize>: Send + Sync + 'static {
+    /// The direction and weight of each distribution, starting with the
+    /// packets at rest.
+    const DIRECTIONS: [(Int3, Float); Q];
+    /// The index of the opposite of each of [`VelocitySet::DIRECTIONS`].
+    const OPPOSITES: [usize; Q] = opposites(&Self::DIRECTIONS);
+
+    /// Each pair of opposite directions once, in the order of their first
+    /// direction.
+    fn pairs() -> Vec<[usize; 2]> {
+        (0..Q)
+            .filter(|&i| i < Self::OPPOSITES[i])
+            .map(|i| [i, Self::OPPOSITES[i]])
+            .collect()
+    }
+}
+
+/// Nine directions in the xy plane, for flows that do not change along z.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct D2Q9;
+
+impl VelocitySet<9> for D2Q9 {
+    const DIRECTIONS: [(Int3, Float); 9] = cubic(2, [4.0 / 9.0, 1.0 / 9.0, 1.0 / 36.0, 0.0]);
+}
+
+/// The faces of a cube, which only carries quantities that diffuse, such as
+/// a [`Scalar`](super::Scalar), since its speed of sound is 1/2 rather than
+/// 1/√3.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct D3Q7;
+
+impl VelocitySet<7> for D3Q7 {
+    const DIRECTIONS: [(Int3, Float); 7] = cubic(3, [1.0 / 4.0, 1.0 / 8.0, 0.0, 0.0]);
+}
+
+/// The faces and corners of a cube, which is the cheapest 3D set for a fluid
+/// but the least isotropic.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct D3Q15;
+
+impl VelocitySet<15> for D3Q15 {
+    const DIRECTIONS: [(Int3, Float); 15] = cubic(3, [2.0 / 9.0, 1.0 / 9.0, 0.0, 1.0 / 72.0]);
+}
+
+/// The faces and edges of a cube.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct D3Q19;
+
+impl VelocitySet<19> for D3Q19 {
+    // https://en.wikipedia.org/wiki/Lattice_Boltzmann_methods#Mathematical_equations_for_simulations
+    const DIRECTIONS: [(Int3, Float); 19] = cubic(3, [1.0 / 3.0, 1.0 / 18.0, 1.0 / 36.0, 0.0]);
+}
+
+/// Every neighbour in a cube, which is the most isotropic set and the best
+/// for turbulence.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct D3Q27;
+
+impl VelocitySet<27> for D3Q27 {
+    const DIRECTIONS: [(Int3, Float); 27] =
+        cubic(3, [8.0 / 27.0, 2.0 / 27.0, 1.0 / 54.0, 1.0 / 216.0]);
+}
+
+/// The directions to the neighbours of a point in the first `dimensions`
+/// axes, where a direction that moves along `n` axes has weight
+/// `weights[n]` and is left out when that is zero.
+///
+/// Directions along fewer axes come first, then those along earlier axes,
+/// with the signs along each axis alternating fastest for the first one.
+const fn cubic<const Q: usize>(dimensions: u32, weights: [Float; 4]) -> [(Int3, Float); Q] {
+    let mut directions = [(Int3::ZERO, 0.0); Q];
+    let mut count = 0;
+    let mut moving = 0;
+    while moving <= dimensions {
+        let weight = weights[moving as usize];
+        let mut axes: u32 = 0;
+        while weight > 0.0 && axes < 1 << dimensions {
+            if axes.count_ones() == moving {
+                let mut signs = 0;
+                while signs < 1 << moving {
+                    let mut c = [0; 3];
+                    let (mut axis, mut j) = (0, 0);
+                    while axis < 3 {
+                        if axes & (1 << axis) != 0 {
+                            c[axis] = if signs & (1 << j) == 0 { 1 } else { -1 };
+                            j += 1;
+                        }
+                        axis += 1;
+                    }
+                    directions[count] = (Int3::new(c[0], c[1], c[2]), weight);
+                    count += 1;
+                    signs += 1;
+                }
+            }
+            axes += 1;
+        }
+        moving += 1;
+    }
+    assert!(count == Q, "the weights do not give Q directions");
+    directions
+}
+
+const fn opposites<const Q: usize>(directions: &[(Int3, Float); Q]) -> [usize; Q] {
+    let mut opposites = [0; Q];
+    let mut i = 0;
+    while i < Q {
+        let (dir, _) = directions[i];
+        let mut j = 0;
+        while j < Q {
+            let (other, _) = directions[j];
+            if other.x == -dir.x && other.y == -dir.y && other.z == -dir.z {
+                opposites[i] = j;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    opposites
+}
+
+#[cfg(test)]
+mod velocity_set_test {
+    use super::{VelocitySet, D2Q9, D3Q15, D3Q19, D3Q27, D3Q7};
+    use crate::{
+        approx_eq,
+        math::{Float, Int3},
+    };
+
+    /// The weights sum to one and give the square of the speed of sound,
+    /// `c2`, in every axis the set moves along.
+    fn check<V: VelocitySet<Q>, const Q: usize>(dimensions: usize, c2: Float) {
+        let directions = V::DIRECTIONS;
+        assert_eq!(directions[0].0, Int3::ZERO);
+        assert!(approx_eq(directions.iter().map(|(_, w)| w).sum(), 1.0));
+        for a in 0..3 {
+            for b in 0..3 {
+                let second: Float = directions
+                    .iter()
+                    .map(|(dir, weight)| {
+                        let c = [dir.x, dir.y, dir.z];
+                        weight * (c[a] * c[b]) as Float
+                    })
+                    .sum();
+                let expected = if a == b && a < dimensions { c2 } else { 0.0 };
+                assert!(approx_eq(second, expected), "{a} {b} {second}");
+            }
+        }
+        for (i, &opposite) in V::OPPOSITES.iter().enumerate() {
+            assert_eq!(directions[opposite].0, -directions[i].0);
+        }
+        assert_eq!(V::pairs().len(), (Q - 1) / 2);
+    }
+
+    #[test]
+    fn sets_are_isotropic() {
+        check::<D2Q9, 9>(2, 1.0 / 3.0);
+        check::<D3Q7, 7>(3, 1.0 / 4.0);
+        check::<D3Q15, 15>(3, 1.0 / 3.0);
+        check::<D3Q19, 19>(3, 1.0 / 3.0);
+        check::<D3Q27, 27>(3, 1.0 / 3.0);
+    }
+
+    #[test]
+    fn d3q19_keeps_its_order() {
+        let directions = D3Q19::DIRECTIONS;
+        assert_eq!(directions[1].0, Int3::new(1, 0, 0));
+        assert_eq!(directions[4].0, Int3::new(0, -1, 0));
+        assert_eq!(directions[8].0, Int3::new(-1, 1, 0));
+        assert_eq!(directions[18].0, Int3::new(0, -1, -1));
+        assert_eq!(D3Q19::pairs()[3], [7, 10]);
+    }
+}
@@ -0,0 +1,386 @@
+use crate::{
+    lbm::{Field, Lattice, VelocitySet},
+    math::{Bound3, Float, Int3, Size3, Vec3},
+    mesh::{Aabb, Bvh, Mesh, Triangle},
+};
+
+/// What occupies a lattice cell.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CellFlag {
+    #[default]
+    Fluid,
+    /// Inside a closed mesh.
+    Solid,
+    /// Fluid with at least one link crossing a mesh.
+    Boundary,
+}
+
+/// A lattice link that crosses a mesh.
+#[derive(Clone, Copy, Debug)]
+pub struct CutLink {
+    /// The cell the link leaves in the first direction of its pair.
+    pub start: Bound3,
+    /// The proportion of the link before the wall.
+    pub proportion: Float,
+    /// Index of the mesh that cuts the link.
+    pub mesh: usize,
+}
+
+/// Meshes rasterized onto the lattice.
+///
+/// Building this tests every link against the meshes, so it is only rebuilt
+/// when the geometry changes. Surfaces passing exactly through lattice points
+/// are supported, but leave those points as fluid.
+pub struct Voxels {
+    pub flags: Field<CellFlag>,
+    /// The cut links of each pair of directions, in the order of
+    /// [`Lattice::iter_pairs`].
+    pub cut_links: Vec<Vec<CutLink>>,
+    /// The hierarchy used to find the cut links, kept for other queries
+    /// against the same meshes.
+    pub bvh: Bvh,
+}
+
+impl Voxels {
+    /// Rasterize `meshes` onto a lattice of velocity set `V`.
+    pub fn new<V: VelocitySet<Q>, const Q: usize>(size: Size3, meshes: &[Mesh]) -> Self {
+        let mut voxels = Self {
+            flags: Field::new(size),
+            cut_links: vec![vec![]; Lattice::<V, Q>::pair_directions().len()],
+            bvh: Bvh::new(&[]),
+        };
+        voxels.rasterize::<V, Q>(meshes, Cells::all(size));
+        voxels
+    }
+
+    /// Rasterize `meshes` again after some of them moved within `bounds`,
+    /// which covers both where they were and where they are now.
+    ///
+    /// Only the cells near `bounds` are tested again. Returns the cells that
+    /// were solid and no longer are.
+    pub fn update<V: VelocitySet<Q>, const Q: usize>(
+        &mut self,
+        meshes: &[Mesh],
+        bounds: Aabb,
+    ) -> Vec<Bound3> {
+        let cells = Cells::within(bounds, self.flags.size());
+        let solid: Vec<_> = cells.iter().filter(|loc| self.is_solid(*loc)).collect();
+        self.rasterize::<V, Q>(meshes, cells);
+        solid
+            .into_iter()
+            .filter(|loc| !self.is_solid(*loc))
+            .collect()
+    }
+
+    pub fn is_solid(&self, loc: Bound3) -> bool {
+        *self.flags.get(loc) == CellFlag::Solid
+    }
+
+    /// Find the solids among `cells` and the links that cross a mesh near
+    /// them, keeping what was found elsewhere.
+    fn rasterize<V: VelocitySet<Q>, const Q: usize>(&mut self, meshes: &[Mesh], cells: Cells) {
+        let size = self.flags.size();
+        self.bvh = Bvh::new(meshes);
+        let bvh = &self.bvh;
+        let closed: Vec<bool> = meshes.iter().map(|mesh| mesh.is_closed()).collect();
+        for loc in cells.iter() {
+            *self.flags.get_mut(loc) = CellFlag::Fluid;
+        }
+        for (i, mesh) in meshes.iter().enumerate() {
+            if closed[i] {
+                fill_solid(&mut self.flags, bvh, i, mesh, cells);
+            }
+        }
+
+        // A link can cross a mesh from a cell just outside its bounds.
+        let starts = cells.grown(size);
+        let pair_directions = Lattice::<V, Q>::pair_directions();
+        let flags = &self.flags;
+        for (links, &dir) in self.cut_links.iter_mut().zip(&pair_directions) {
+            links.retain(|link| !starts.contains(link.start));
+            for start in starts.iter() {
+                let p0 = Vec3::from(Int3::from(start));
+                let p1 = p0 + dir.into();
+                let Some(hit) = bvh.nearest_hit(p0, p1) else {
+                    continue;
+                };
+                // A closed surface that only touches the end of a link cuts it
+                // when the other end is inside. Open surfaces cut the links
+                // leaving the points they pass through.
+                let touching = hit.proportion == 0.0 || hit.proportion == 1.0;
+                let cut = match closed[hit.mesh] {
+                    _ if !touching => true,
+                    true => {
+                        let end = (Int3::from(start) + dir).wrap(size);
+                        [start, end]
+                            .iter()
+                            .any(|cell| *flags.get(*cell) == CellFlag::Solid)
+                    }
+                    false => hit.proportion == 0.0,
+                };
+                if cut {
+                    links.push(CutLink {
+                        start,
+                        proportion: hit.proportion,
+                        mesh: hit.mesh,
+                    });
+                }
+            }
+        }
+
+        // The ends of the links may have wrapped around to anywhere, so every
+        // boundary is marked again.
+        for flag in &mut self.flags.values {
+            if *flag == CellFlag::Boundary {
+                *flag = CellFlag::Fluid;
+            }
+        }
+        for (links, dir) in self.cut_links.iter().zip(pair_directions) {
+            for link in links {
+                let end = (Int3::from(link.start) + dir).wrap(size);
+                for cell in [link.start, end] {
+                    let flag = self.flags.get_mut(cell);
+                    if *flag == CellFlag::Fluid {
+                        *flag = CellFlag::Boundary;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A box of cells, from `min` up to but not including `max` along each axis.
+#[derive(Clone, Copy, Debug)]
+struct Cells {
+    min: [usize; 3],
+    max: [usize; 3],
+}
+
+impl Cells {
+    fn all(size: Size3) -> Self {
+        Self {
+            min: [0; 3],
+            max: [size.x, size.y, size.z],
+        }
+    }
+
+    /// The cells of the grid inside `bounds`.
+    fn within(bounds: Aabb, size: Size3) -> Self {
+        let (min, max) = (bounds.min, bounds.max);
+        let first = |v: Float, len: usize| v.ceil().clamp(0.0, len as Float) as usize;
+        let end = |v: Float, len: usize| (v.floor() + 1.0).clamp(0.0, len as Float) as usize;
+        Self {
+            min: [
+                first(min.x, size.x),
+                first(min.y, size.y),
+                first(min.z, size.z),
+            ],
+            max: [end(max.x, size.x), end(max.y, size.y), end(max.z, size.z)],
+        }
+    }
+
+    /// These cells and their neighbours in the grid.
+    fn grown(self, size: Size3) -> Self {
+        let len = [size.x, size.y, size.z];
+        Self {
+            min: self.min.map(|min| min.saturating_sub(1)),
+            max: std::array::from_fn(|i| (self.max[i] + 1).min(len[i])),
+        }
+    }
+
+    fn contains(&self, loc: Bound3) -> bool {
+        let coords = [loc.x(), loc.y(), loc.z()];
+        (0..3).all(|i| self.min[i] <= coords[i] && coords[i] < self.max[i])
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Bound3> {
+        let Self { min, max } = *self;
+        let size = Size3::new(max[0], max[1], max[2]);
+        (min[0]..max[0])
+            .flat_map(move |x| (min[1]..max[1]).map(move |y| (x, y)))
+            .flat_map(move |(x, y)| (min[2]..max[2]).map(move |z| size.bound(x, y, z).unwrap()))
+    }
+}
+
+/// Mark the cells among `cells` inside a closed mesh as solid.
+///
+/// Each row of cells along x is inside wherever it has crossed the surface an
+/// odd number of times. Cells lying exactly on the surface are left as fluid.
+fn fill_solid(flags: &mut Field<CellFlag>, bvh: &Bvh, index: usize, mesh: &Mesh, cells: Cells) {
+    let Some(Aabb { min, max }) = mesh.bounds() else {
+        return;
+    };
+    let size = flags.size();
+    let mut crossings = vec![];
+    for y in cells.min[1]..cells.max[1] {
+        for z in cells.min[2]..cells.max[2] {
+            let (y_f, z_f) = (y as Float, z as Float);
+            if y_f < min.y || y_f > max.y || z_f < min.z || z_f > max.z {
+                continue;
+            }
+            let p0 = Vec3::new(min.x - 1.0, y_f, z_f);
+            let p1 = Vec3::new(max.x + 1.0, y_f, z_f);
+            let hits: Vec<_> = bvh
+                .hits(p0, p1)
+                .into_iter()
+                .filter(|hit| hit.mesh == index)
+                .collect();
+            // The row starts outside the mesh, so a hit at its start means it
+            // runs along the surface and every cell on it is on the surface.
+            if hits.iter().any(|hit| hit.proportion == 0.0) {
+                continue;
+            }
+            crossings.clear();
+            crossings.extend(
+                hits.iter()
+                    .filter(|hit| row_crosses(&mesh.triangles[hit.triangle], y_f, z_f))
+                    .map(|hit| p0.x + hit.proportion * (p1.x - p0.x)),
+            );
+            crossings.sort_by(|a, b| a.total_cmp(b));
+            for x in cells.min[0]..cells.max[0] {
+                let x_f = x as Float;
+                let on_surface = crossings.iter().any(|c| crate::approx_eq(*c, x_f));
+                let before = crossings.iter().filter(|c| **c < x_f).count();
+                if !on_surface && before % 2 == 1 {
+                    *flags.get_mut(size.bound(x, y, z).unwrap()) = CellFlag::Solid;
+                }
+            }
+        }
+    }
+}
+
+/// Whether the row along x through `y` and `z` crosses `triangle` once it is
+/// nudged off the triangle's edges by an infinitely small step.
+///
+/// A row through an edge or corner then crosses one of the triangles that
+/// meet there where it passes through the surface, and none or two of them
+/// where it only grazes it, whichever way the triangles face.
+fn row_crosses(triangle: &Triangle, y: Float, z: Float) -> bool {
+    // The corners seen along the row, relative to it.
+    let [a, b, c] = triangle.points().map(|p| (p.y - y, p.z - z));
+    // The side of the edge from `p` to `q` that the row is on, moving it by
+    // (ε, ε²) when it lies on the edge.
+    let side = |(pu, pv): (Float, Float), (qu, qv): (Float, Float)| {
+        let (du, dv) = (qu - pu, qv - pv);
+        let cross = dv * pu - du * pv;
+        let nudged = if cross != 0.0 {
+            cross
+        } else if dv != 0.0 {
+            -dv
+        } else {
+            du
+        };
+        nudged.partial_cmp(&0.0)
+    };
+    let sides = [side(a, b), side(b, c), side(c, a)];
+    sides[0] != Some(std::cmp::Ordering::Equal) && sides.iter().all(|s| *s == sides[0])
+}
+
+#[cfg(test)]
+mod voxel_test {
+    use super::{CellFlag, Voxels};
+    use crate::{
+        lbm::{D2Q9, D3Q19},
+        math::{Size3, Vec3},
+        mesh::Mesh,
+    };
+
+    const SIZE: Size3 = Size3::new(8, 8, 8);
+
+    #[test]
+    fn cuboid_voxels() {
+        let cuboid = Mesh::cuboid(Vec3::new(2.5, 2.5, 2.5), Vec3::new(5.5, 4.5, 3.5));
+        let voxels = Voxels::new::<D3Q19, 19>(SIZE, &[cuboid]);
+        let flag = |x, y, z| *voxels.flags.get(SIZE.bound(x, y, z).unwrap());
+        assert_eq!(flag(3, 3, 3), CellFlag::Solid);
+        assert_eq!(flag(5, 4, 3), CellFlag::Solid);
+        assert_eq!(flag(2, 3, 3), CellFlag::Boundary);
+        assert_eq!(flag(6, 4, 3), CellFlag::Boundary);
+        assert_eq!(flag(0, 0, 0), CellFlag::Fluid);
+        // Links into each side of the 3x2x1 block along each axis.
+        assert_eq!(voxels.cut_links[0].len(), 2 * 2);
+        assert_eq!(voxels.cut_links[1].len(), 2 * 3);
+        assert_eq!(voxels.cut_links[2].len(), 2 * 6);
+    }
+
+    #[test]
+    fn update_matches_rebuild() {
+        let at = |x| Mesh::cuboid(Vec3::new(x, 2.5, 2.5), Vec3::new(x + 2.0, 4.5, 3.5));
+        let mut voxels = Voxels::new::<D3Q19, 19>(SIZE, &[at(1.5), at(4.5)]);
+        // The first block slides over by a cell and a bit, up to the other.
+        let moved = [at(2.7), at(4.5)];
+        let swept = at(1.5).bounds().unwrap().union(moved[0].bounds().unwrap());
+        let uncovered = voxels.update::<D3Q19, 19>(&moved, swept);
+        assert_eq!(uncovered.len(), 2);
+        assert!(uncovered.iter().all(|loc| loc.x() == 2));
+        let rebuilt = Voxels::new::<D3Q19, 19>(SIZE, &moved);
+        assert_eq!(voxels.flags.values, rebuilt.flags.values);
+        for (links, expected) in voxels.cut_links.iter().zip(&rebuilt.cut_links) {
+            assert_eq!(links.len(), expected.len());
+            for link in expected {
+                assert!(links.iter().any(|l| l.start == link.start
+                    && l.mesh == link.mesh
+                    && l.proportion == link.proportion));
+            }
+        }
+    }
+
+    #[test]
+    fn cuboid_on_lattice_points() {
+        let cuboid = Mesh::cuboid(Vec3::new(2.0, 2.0, 2.0), Vec3::new(5.0, 5.0, 5.0));
+        let voxels = Voxels::new::<D3Q19, 19>(SIZE, &[cuboid]);
+        let flag = |x, y, z| *voxels.flags.get(SIZE.bound(x, y, z).unwrap());
+        assert_eq!(flag(3, 3, 3), CellFlag::Solid);
+        assert_eq!(flag(2, 3, 3), CellFlag::Boundary);
+        assert_eq!(flag(1, 3, 3), CellFlag::Fluid);
+        // Only links from the surface into the 2x2x2 inside along each axis.
+        assert_eq!(voxels.cut_links[0].len(), 2 * 4);
+    }
+
+    #[test]
+    fn polyline_voxels() {
+        let size = Size3::new(8, 8, 1);
+        let square = Mesh::polyline(&[[1.5, 1.5], [4.5, 1.5], [4.5, 3.5], [1.5, 3.5]], true);
+        let voxels = Voxels::new::<D2Q9, 9>(size, &[square]);
+        let flag = |x, y| *voxels.flags.get(size.bound(x, y, 0).unwrap());
+        assert_eq!(flag(2, 2), CellFlag::Solid);
+        assert_eq!(flag(4, 3), CellFlag::Solid);
+        assert_eq!(flag(1, 2), CellFlag::Boundary);
+        assert_eq!(flag(0, 0), CellFlag::Fluid);
+        // Links into each side of the 3x2 square along x and y.
+        assert_eq!(voxels.cut_links[0].len(), 2 * 2);
+        assert_eq!(voxels.cut_links[1].len(), 2 * 3);
+    }
+
+    #[test]
+    fn rows_through_corners() {
+        // Rows 0 and 4 only touch the top and bottom corners, while row 2
+        // passes through the side corners and row 1 through the edges that
+        // split each wall into triangles.
+        let size = Size3::new(8, 6, 1);
+        let diamond = Mesh::polyline(&[[2.0, 0.0], [4.0, 2.0], [2.0, 4.0], [0.0, 2.0]], true);
+        let voxels = Voxels::new::<D2Q9, 9>(size, &[diamond]);
+        for x in 0..size.x {
+            for y in 0..size.y {
+                let inside = (x as i32 - 2).abs() + (y as i32 - 2).abs() < 2;
+                let flag = *voxels.flags.get(size.bound(x, y, 0).unwrap());
+                assert_eq!(flag == CellFlag::Solid, inside, "{x} {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn open_mesh_has_no_solid() {
+        let mut plane = Mesh::cuboid(Vec3::new(2.5, 0.0, 0.0), Vec3::new(2.5, 8.0, 8.0));
+        plane.triangles.truncate(2);
+        let voxels = Voxels::new::<D3Q19, 19>(SIZE, &[plane]);
+        assert_eq!(
+            *voxels.flags.get(SIZE.bound(3, 3, 3).unwrap()),
+            CellFlag::Boundary
+        );
+        assert_eq!(
+            *voxels.flags.get(SIZE.bound(5, 3, 3).unwrap()),
+            CellFlag::Fluid
+        );
+    }
+}
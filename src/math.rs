@@ -5,13 +5,22 @@ use std::{
 
 use crate::approx_eq;
 
+#[cfg(not(feature = "f64"))]
 pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+
+/// Mathematical constants at the precision of [`Float`].
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
 
 pub fn lerp(a: Float, b: Float, mix: Float) -> Float {
     b * mix + a * (1.0 - mix)
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vec3 {
     pub x: Float,
     pub y: Float,
@@ -19,11 +28,11 @@ pub struct Vec3 {
 }
 
 impl Vec3 {
-    pub fn wrap(self, (x, y, z): (usize, usize, usize)) -> Self {
+    pub fn wrap(self, size: Size3) -> Self {
         (
-            self.x.rem_euclid(x as Float),
-            self.y.rem_euclid(y as Float),
-            self.z.rem_euclid(z as Float),
+            self.x.rem_euclid(size.x as Float),
+            self.y.rem_euclid(size.y as Float),
+            self.z.rem_euclid(size.z as Float),
         )
             .into()
     }
@@ -67,8 +76,8 @@ impl Default for Vec3 {
     }
 }
 
-impl From<(f32, f32, f32)> for Vec3 {
-    fn from((x, y, z): (f32, f32, f32)) -> Self {
+impl From<(Float, Float, Float)> for Vec3 {
+    fn from((x, y, z): (Float, Float, Float)) -> Self {
         Self { x, y, z }
     }
 }
@@ -180,13 +189,14 @@ impl Int3 {
     pub const fn new(x: i32, y: i32, z: i32) -> Self {
         Self { x, y, z }
     }
-    pub fn wrap<const X: usize, const Y: usize, const Z: usize>(self) -> Bound3<X, Y, Z> {
-        Bound3::new(
-            self.x.rem_euclid(X as i32) as usize,
-            self.y.rem_euclid(Y as i32) as usize,
-            self.z.rem_euclid(Z as i32) as usize,
-        )
-        .unwrap()
+    /// The point in a grid of `size` that is equal to this one modulo each
+    /// dimension.
+    pub fn wrap(self, size: Size3) -> Bound3 {
+        Bound3 {
+            x: self.x.rem_euclid(size.x as i32) as usize,
+            y: self.y.rem_euclid(size.y as i32) as usize,
+            z: self.z.rem_euclid(size.z as i32) as usize,
+        }
     }
 }
 impl From<(i32, i32, i32)> for Int3 {
@@ -200,25 +210,71 @@ impl Add<Int3> for Int3 {
         Self::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
     }
 }
+impl Sub<Int3> for Int3 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+impl Neg for Int3 {
+    type Output = Self;
+    fn neg(self) -> Self::Output {
+        Self::new(-self.x, -self.y, -self.z)
+    }
+}
+impl From<Bound3> for Int3 {
+    fn from(value: Bound3) -> Self {
+        Self::new(value.x as i32, value.y as i32, value.z as i32)
+    }
+}
 impl From<Int3> for Vec3 {
     fn from(value: Int3) -> Self {
         Self::new(value.x as Float, value.y as Float, value.z as Float)
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-pub struct Bound3<const X: usize, const Y: usize, const Z: usize> {
-    x: usize,
-    y: usize,
-    z: usize,
+/// The number of points along each axis of a grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Size3 {
+    pub x: usize,
+    pub y: usize,
+    pub z: usize,
 }
 
-impl<const X: usize, const Y: usize, const Z: usize> Bound3<X, Y, Z> {
+impl Size3 {
+    pub const fn new(x: usize, y: usize, z: usize) -> Self {
+        Self { x, y, z }
+    }
+
+    /// The number of points in the grid.
+    pub const fn count(&self) -> usize {
+        self.x * self.y * self.z
+    }
+
     #[allow(clippy::result_unit_err)]
-    pub fn new(x: usize, y: usize, z: usize) -> Result<Self, ()> {
-        (x, y, z).try_into()
+    pub fn bound(&self, x: usize, y: usize, z: usize) -> Result<Bound3, ()> {
+        if x >= self.x || y >= self.y || z >= self.z {
+            return Err(());
+        }
+        Ok(Bound3 { x, y, z })
+    }
+
+    /// The position of `loc` in a buffer with one value per point, ordered by
+    /// x, then y, then z.
+    pub fn index(&self, loc: Bound3) -> usize {
+        (loc.x * self.y + loc.y) * self.z + loc.z
     }
+}
+
+/// A point inside a grid, made by [`Size3::bound`] or [`Int3::wrap`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bound3 {
+    x: usize,
+    y: usize,
+    z: usize,
+}
 
+impl Bound3 {
     pub fn x(&self) -> usize {
         self.x
     }
@@ -230,29 +286,33 @@ impl<const X: usize, const Y: usize, const Z: usize> Bound3<X, Y, Z> {
     }
 }
 
-impl<const X: usize, const Y: usize, const Z: usize> TryFrom<(usize, usize, usize)>
-    for Bound3<X, Y, Z>
-{
-    type Error = ();
-    fn try_from((x, y, z): (usize, usize, usize)) -> Result<Self, Self::Error> {
-        if x >= X || y >= Y || z >= Z {
-            return Err(());
-        }
-        Ok(Self { x, y, z })
+#[cfg(test)]
+mod size_test {
+    use super::{Int3, Size3};
+
+    #[test]
+    fn bound_checks_each_axis() {
+        let size = Size3::new(4, 3, 2);
+        assert!(size.bound(3, 2, 1).is_ok());
+        assert!(size.bound(4, 0, 0).is_err());
+        assert!(size.bound(0, 3, 0).is_err());
+        assert!(size.bound(0, 0, 2).is_err());
     }
-}
 
-impl<const X: usize, const Y: usize, const Z: usize> TryFrom<Int3> for Bound3<X, Y, Z> {
-    type Error = ();
-    fn try_from(Int3 { x, y, z }: Int3) -> Result<Self, Self::Error> {
-        if x < 0 || y < 0 || z < 0 {
-            return Err(());
-        }
-        let (x, y, z) = (x as usize, y as usize, z as usize);
-        if x >= X || y >= Y || z >= Z {
-            return Err(());
+    #[test]
+    fn wrap_and_index_cover_grid() {
+        let size = Size3::new(4, 3, 2);
+        let wrapped = Int3::new(-1, 4, -3).wrap(size);
+        assert_eq!(wrapped, size.bound(3, 1, 1).unwrap());
+        let mut seen = vec![false; size.count()];
+        for x in 0..size.x {
+            for y in 0..size.y {
+                for z in 0..size.z {
+                    seen[size.index(size.bound(x, y, z).unwrap())] = true;
+                }
+            }
         }
-        Ok(Self { x, y, z })
+        assert!(seen.iter().all(|seen| *seen));
     }
 }
 
@@ -264,6 +324,20 @@ impl Matrix3 {
     pub const IDENTITY: Matrix3 = Matrix3 {
         rows: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
     };
+    /// A rotation by `angle` radians around `axis`, following the right hand
+    /// rule.
+    pub fn rotation(axis: Vec3, angle: Float) -> Self {
+        let Vec3 { x, y, z } = axis.normalized();
+        let (sin, cos) = angle.sin_cos();
+        let t = 1.0 - cos;
+        Self {
+            rows: [
+                [t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y],
+                [t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x],
+                [t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos],
+            ],
+        }
+    }
     pub fn from_columns(col0: Vec3, col1: Vec3, col2: Vec3) -> Self {
         Self {
             rows: [
@@ -273,6 +347,26 @@ impl Matrix3 {
             ],
         }
     }
+    pub fn diagonal(d: Vec3) -> Self {
+        Self {
+            rows: [[d.x, 0.0, 0.0], [0.0, d.y, 0.0], [0.0, 0.0, d.z]],
+        }
+    }
+    pub fn column(&self, i: usize) -> Vec3 {
+        Vec3::new(self.rows[0][i], self.rows[1][i], self.rows[2][i])
+    }
+    pub fn transpose(&self) -> Self {
+        Self::from_columns(
+            Vec3::from_slice(self.rows[0]),
+            Vec3::from_slice(self.rows[1]),
+            Vec3::from_slice(self.rows[2]),
+        )
+    }
+    /// The nearest rotation, correcting drift from repeated multiplication.
+    pub fn orthonormalized(&self) -> Self {
+        let (col0, col1) = self.column(0).orthonormal(self.column(1));
+        Self::from_columns(col0, col1, col0.cross(col1))
+    }
     fn det2x2(a: Float, b: Float, c: Float, d: Float) -> Float {
         a * d - b * c
     }
@@ -359,7 +453,7 @@ impl Mul<Vec3> for &Matrix3 {
         )
     }
 }
-impl Mul<Matrix3> for f32 {
+impl Mul<Matrix3> for Float {
     type Output = Matrix3;
 
     fn mul(self, m: Matrix3) -> Self::Output {
@@ -370,7 +464,7 @@ impl Mul<Matrix3> for f32 {
 }
 #[cfg(test)]
 mod mat_test {
-    use crate::math::{Matrix3, Vec3};
+    use crate::math::{consts, Matrix3, Vec3};
 
     #[test]
     fn inverse_of_identity() {
@@ -399,6 +493,19 @@ mod mat_test {
         );
     }
     #[test]
+    fn rotation_about_z() {
+        let rotation = Matrix3::rotation(Vec3::new(0.0, 0.0, 2.0), consts::FRAC_PI_2);
+        let rotated = &rotation * Vec3::new(1.0, 0.0, 0.0);
+        assert!(rotated.approx_eq(Vec3::new(0.0, 1.0, 0.0)), "got {rotated}");
+    }
+    #[test]
+    fn rotation_is_orthonormal() {
+        let rotation = Matrix3::rotation(Vec3::new(1.0, 2.0, 3.0), 0.7);
+        let product = rotation.clone() * rotation.transpose();
+        assert!(product.approx_eq(Matrix3::IDENTITY));
+        assert!(rotation.orthonormalized().approx_eq(rotation));
+    }
+    #[test]
     fn invert_of_matrix() {
         let scaling = Matrix3::from_columns(
             Vec3::new(2.0, 2.0, 3.0),
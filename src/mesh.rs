@@ -1,12 +1,224 @@
+mod bvh;
+mod import;
+
+use std::collections::HashMap;
+
 use crate::{
     math::{Matrix3, Vec3},
     Float,
 };
 
+pub use bvh::{Aabb, Bvh, Hit};
+pub use import::{ImportError, Placement};
+
+#[derive(Clone, Debug)]
 pub struct Mesh {
     pub triangles: Vec<Triangle>,
+    /// Velocity of the surface in lattice units per step.
+    ///
+    /// Without a `rigid_body` this only changes how the fluid is pushed by
+    /// the walls, and the triangles stay where they are. A rigid body
+    /// integrates it each step and moves its triangles along with it.
+    pub velocity: Vec3,
+    /// Angular velocity in radians per step about `center`.
+    pub angular_velocity: Vec3,
+    pub center: Vec3,
+    /// Set for meshes that are moved by the forces on them.
+    pub rigid_body: Option<RigidBody>,
+}
+
+/// The mass and orientation of a mesh that is moved by the forces on it.
+///
+/// The mesh's `center` is taken as its center of mass, and is integrated
+/// along with its `velocity` and `angular_velocity`.
+#[derive(Clone, Debug)]
+pub struct RigidBody {
+    pub mass: Float,
+    /// The inertia tensor about the center of mass, in the body's frame.
+    pub inertia: Matrix3,
+    /// The rotation from the body's frame to the lattice.
+    pub orientation: Matrix3,
+    /// The triangles in the body's frame, relative to the center of mass.
+    shape: Vec<Triangle>,
+}
+
+impl RigidBody {
+    /// The inertia tensor of a solid box, which with a small depth also works
+    /// for a flat plate.
+    pub fn box_inertia(mass: Float, size: Vec3) -> Matrix3 {
+        let Vec3 { x, y, z } = size;
+        Matrix3::diagonal((mass / 12.0) * Vec3::new(y * y + z * z, x * x + z * z, x * x + y * y))
+    }
+}
+
+impl Mesh {
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        Self {
+            triangles,
+            velocity: Vec3::ZERO,
+            angular_velocity: Vec3::ZERO,
+            center: Vec3::ZERO,
+            rigid_body: None,
+        }
+    }
+    /// Let the mesh be moved by the forces on it, starting from where it is
+    /// now with its `center` as the center of mass.
+    ///
+    /// The forces are applied explicitly, which is unstable for bodies that
+    /// are not much denser than the fluid they displace.
+    pub fn with_rigid_body(mut self, mass: Float, inertia: Matrix3) -> Self {
+        let center = self.center;
+        let shape = self
+            .triangles
+            .iter()
+            .map(|t| {
+                let [p0, p1, p2] = t.points().map(|p| p - center);
+                Triangle::new(p0, p1, p2)
+            })
+            .collect();
+        self.rigid_body = Some(RigidBody {
+            mass,
+            inertia,
+            orientation: Matrix3::IDENTITY,
+            shape,
+        });
+        self
+    }
+    /// Move a rigid body by one step under a force and a torque about its
+    /// center, returning whether the mesh moved.
+    ///
+    /// A body at rest keeps its triangles as they are.
+    pub fn advance(&mut self, force: Vec3, torque: Vec3) -> bool {
+        let Some(body) = &mut self.rigid_body else {
+            return false;
+        };
+        self.velocity = self.velocity + force / body.mass;
+        self.center = self.center + self.velocity;
+
+        // Euler's equations, with the inertia tensor turned into the lattice frame.
+        let inertia =
+            body.orientation.clone() * body.inertia.clone() * body.orientation.transpose();
+        let spin = &inertia * self.angular_velocity;
+        self.angular_velocity = self.angular_velocity
+            + &inertia.inverse() * (torque - self.angular_velocity.cross(spin));
+        let angle = self.angular_velocity.dot(self.angular_velocity).sqrt();
+        if angle > 0.0 {
+            body.orientation = (Matrix3::rotation(self.angular_velocity, angle)
+                * body.orientation.clone())
+            .orthonormalized();
+        } else if self.velocity == Vec3::ZERO {
+            return false;
+        }
+
+        self.triangles = body
+            .shape
+            .iter()
+            .map(|t| {
+                let [p0, p1, p2] = t.points().map(|p| &body.orientation * p + self.center);
+                Triangle::new(p0, p1, p2)
+            })
+            .collect();
+        true
+    }
+    /// An axis aligned box between two opposite corners.
+    pub fn cuboid(min: Vec3, max: Vec3) -> Self {
+        let corner = |i: usize| {
+            Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+        // Two triangles for each face, indexed by corner bits.
+        const FACES: [[usize; 4]; 6] = [
+            [0, 2, 6, 4],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 3, 7, 6],
+            [0, 1, 3, 2],
+            [4, 5, 7, 6],
+        ];
+        let mut mesh = Self::new(
+            FACES
+                .iter()
+                .flat_map(|[a, b, c, d]| {
+                    [
+                        Triangle::new(corner(*a), corner(*b), corner(*c)),
+                        Triangle::new(corner(*a), corner(*c), corner(*d)),
+                    ]
+                })
+                .collect(),
+        );
+        mesh.center = 0.5 * (min + max);
+        mesh
+    }
+    /// A wall along a path in the xy plane for a flat simulation, with an
+    /// upright rectangle from z = -0.5 to 0.5 for each segment.
+    ///
+    /// A `closed` path also joins its last point back to the first and is
+    /// capped at both ends, so that the points inside it are solid.
+    pub fn polyline(points: &[[Float; 2]], closed: bool) -> Self {
+        let at = |[x, y]: [Float; 2], z: Float| Vec3::new(x, y, z);
+        let (bottom, top) = (|p| at(p, -0.5), |p| at(p, 0.5));
+        let closing = (closed && points.len() > 2).then(|| [points[points.len() - 1], points[0]]);
+        let mut triangles: Vec<_> = points
+            .windows(2)
+            .map(|pair| [pair[0], pair[1]])
+            .chain(closing)
+            .flat_map(|[a, b]| {
+                [
+                    Triangle::new(bottom(a), bottom(b), top(b)),
+                    Triangle::new(bottom(a), top(b), top(a)),
+                ]
+            })
+            .collect();
+        if closing.is_some() {
+            // The caps are never crossed by the links of a flat lattice, so
+            // a fan closes the surface even when the path is not convex.
+            for pair in points[1..].windows(2) {
+                triangles.push(Triangle::new(
+                    bottom(points[0]),
+                    bottom(pair[1]),
+                    bottom(pair[0]),
+                ));
+                triangles.push(Triangle::new(top(points[0]), top(pair[0]), top(pair[1])));
+            }
+        }
+        let mut mesh = Self::new(triangles);
+        let sum = points
+            .iter()
+            .fold(Vec3::ZERO, |sum, &point| sum + at(point, 0.0));
+        mesh.center = sum / points.len().max(1) as Float;
+        mesh
+    }
+    /// The velocity of the surface at a point on the mesh.
+    pub fn velocity_at(&self, point: Vec3) -> Vec3 {
+        self.velocity + self.angular_velocity.cross(point - self.center)
+    }
+    pub fn bounds(&self) -> Option<Aabb> {
+        Aabb::from_points(self.triangles.iter().flat_map(|t| t.points()))
+    }
+    /// Whether the mesh encloses a volume, which is when every edge is shared
+    /// by exactly two triangles.
+    ///
+    /// Vertices are matched exactly, so meshes built from separately rounded
+    /// points will not be considered closed.
+    pub fn is_closed(&self) -> bool {
+        let key = |p: Vec3| [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+        let mut edges = HashMap::new();
+        for [p0, p1, p2] in self.triangles.iter().map(|t| t.points()) {
+            for (a, b) in [(p0, p1), (p1, p2), (p2, p0)] {
+                let (a, b) = (key(a), key(b));
+                *edges
+                    .entry(if a < b { (a, b) } else { (b, a) })
+                    .or_insert(0) += 1;
+            }
+        }
+        !edges.is_empty() && edges.values().all(|&count| count == 2)
+    }
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct Triangle {
     p0: Vec3,
     p1: Vec3,
@@ -17,6 +229,12 @@ impl Triangle {
     pub fn new(p0: Vec3, p1: Vec3, p2: Vec3) -> Self {
         Self { p0, p1, p2 }
     }
+    pub fn points(&self) -> [Vec3; 3] {
+        [self.p0, self.p1, self.p2]
+    }
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_points(self.points()).unwrap()
+    }
     // TODO: numerical error with almost parallel?
     /// Check if the triangle intersects a line segment.
     ///
@@ -35,12 +253,13 @@ impl Triangle {
         if normal_component_prod > 0.0 {
             return None;
         }
-        // if the line segment touches the triangle (normal component is 0)
-        if l0_prime.z == 0.0 {
-            return Some(0.0);
-        }
-        if l1_prime.z == 0.0 {
-            return Some(1.0);
+        // if the line segment touches the plane of the triangle (normal
+        // component is 0), it has to do so within the triangle.
+        match (l0_prime.z == 0.0, l1_prime.z == 0.0) {
+            (true, true) => return coplanar_overlap(l0_prime, l1_prime).then_some(0.0),
+            (true, false) => return in_triangle(l0_prime.x, l0_prime.y).then_some(0.0),
+            (false, true) => return in_triangle(l1_prime.x, l1_prime.y).then_some(1.0),
+            (false, false) => {}
         }
         let difference = l1_prime - l0_prime;
         // We want to know where the line segment intersects the triangle
@@ -53,22 +272,48 @@ impl Triangle {
         let t1_component = line_scale * difference.y + l0_prime.y;
         let (c0, c1) = (t0_component, t1_component);
 
-        // For the intersection to lie in the triangle, each component must
-        // be between 0 and 1, and the sum must be less than 1.
-        if (0.0..=1.0).contains(&c0)
-            && (0.0..=1.0).contains(&c1)
-            && (0.0..=1.0).contains(&(c0 + c1))
-        {
-            Some(line_scale)
+        in_triangle(c0, c1).then_some(line_scale)
+    }
+}
+
+/// For a point to lie in the triangle, each component in terms of the edges
+/// must be between 0 and 1, and the sum must be less than 1.
+fn in_triangle(c0: Float, c1: Float) -> bool {
+    (0.0..=1.0).contains(&c0) && (0.0..=1.0).contains(&c1) && (0.0..=1.0).contains(&(c0 + c1))
+}
+
+/// Whether a line segment lying in the plane of a triangle overlaps it, with
+/// both ends given in terms of the triangle edges.
+fn coplanar_overlap(l0: Vec3, l1: Vec3) -> bool {
+    // Clip the segment against each of the three sides in turn.
+    let (mut enter, mut exit): (Float, Float) = (0.0, 1.0);
+    for (start, end) in [
+        (l0.x, l1.x),
+        (l0.y, l1.y),
+        (1.0 - l0.x - l0.y, 1.0 - l1.x - l1.y),
+    ] {
+        let delta = end - start;
+        if delta == 0.0 {
+            if start < 0.0 {
+                return false;
+            }
+            continue;
+        }
+        let t = -start / delta;
+        if delta > 0.0 {
+            enter = enter.max(t);
         } else {
-            None
+            exit = exit.min(t);
         }
     }
+    enter <= exit
 }
 
 #[cfg(test)]
 mod mesh_test {
-    use super::{Triangle, Vec3};
+    use crate::math::consts::FRAC_PI_2;
+
+    use super::{Mesh, RigidBody, Triangle, Vec3};
 
     #[test]
     fn triangle_intersect_tests() {
@@ -108,5 +353,54 @@ mod mesh_test {
         assert!(triangle
             .intersect_proportion(Vec3::new(0.1, 0.1, 0.1), Vec3::new(0.1, 0.1, 1.0))
             .is_none());
+        // Touching the plane of the triangle away from the triangle.
+        assert!(triangle
+            .intersect_proportion(Vec3::new(2.0, 2.0, 0.0), Vec3::new(2.0, 2.0, 1.0))
+            .is_none());
+        // Lying in the plane of the triangle away from the triangle.
+        assert!(triangle
+            .intersect_proportion(Vec3::new(2.0, 2.0, 0.0), Vec3::new(3.0, 2.0, 0.0))
+            .is_none());
+    }
+
+    #[test]
+    fn rigid_body_moves() {
+        let cuboid = Mesh::cuboid(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0));
+        let inertia = RigidBody::box_inertia(2.0, Vec3::new(2.0, 2.0, 2.0));
+        let mut body = cuboid.with_rigid_body(2.0, inertia);
+        assert!(!body.advance(Vec3::ZERO, Vec3::ZERO));
+        assert!(body.advance(Vec3::new(1.0, 0.0, 0.0), Vec3::ZERO));
+        assert!(body.velocity.approx_eq(Vec3::new(0.5, 0.0, 0.0)));
+        assert!(body.center.approx_eq(Vec3::new(0.5, 0.0, 0.0)));
+        let bounds = body.bounds().unwrap();
+        assert!(bounds.min.approx_eq(Vec3::new(-0.5, -1.0, -1.0)));
+
+        // A quarter turn about z, from a torque that spins it up in one step.
+        let spin = FRAC_PI_2 * 4.0 / 3.0;
+        body.advance(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, spin));
+        assert!(body
+            .angular_velocity
+            .approx_eq(Vec3::new(0.0, 0.0, FRAC_PI_2)));
+        let corner = body.triangles[0].points()[0] - body.center;
+        assert!(corner.approx_eq(Vec3::new(1.0, -1.0, -1.0)), "{corner}");
+    }
+
+    #[test]
+    fn cuboid_is_closed() {
+        let cuboid = Mesh::cuboid(Vec3::new(0.5, 0.5, 0.5), Vec3::new(2.5, 3.5, 4.5));
+        assert!(cuboid.is_closed());
+        let open = Mesh::new(cuboid.triangles[1..].to_vec());
+        assert!(!open.is_closed());
+    }
+
+    #[test]
+    fn closed_polyline_is_closed() {
+        // Not convex, which the caps do not need.
+        let points = [[0.0, 0.0], [4.0, 0.0], [4.0, 3.0], [2.0, 1.0], [0.0, 3.0]];
+        assert!(Mesh::polyline(&points, true).is_closed());
+        let open = Mesh::polyline(&points, false);
+        assert!(!open.is_closed());
+        assert_eq!(open.triangles.len(), 2 * 4);
+        assert!(open.center.approx_eq(Vec3::new(2.0, 1.4, 0.0)));
     }
 }
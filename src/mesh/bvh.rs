@@ -0,0 +1,347 @@
+use std::cell::Cell;
+
+use crate::{
+    math::Vec3,
+    mesh::{Mesh, Triangle},
+    Float,
+};
+
+/// Padding added to every box so that hits touching a box are not missed.
+const PADDING: Float = 0.0001;
+/// The most triangles kept in a single leaf.
+const LEAF_SIZE: usize = 4;
+
+/// An axis aligned bounding box.
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(first, first), |aabb, p| {
+            aabb.union(Self::new(p, p))
+        }))
+    }
+    pub fn union(self, other: Self) -> Self {
+        Self::new(
+            Vec3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Vec3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+    pub fn center(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+    pub fn contains(&self, point: Vec3) -> bool {
+        self.min.x <= point.x
+            && point.x <= self.max.x
+            && self.min.y <= point.y
+            && point.y <= self.max.y
+            && self.min.z <= point.z
+            && point.z <= self.max.z
+    }
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+            && self.min.z <= other.max.z
+            && other.min.z <= self.max.z
+    }
+    fn padded(self) -> Self {
+        let padding = Vec3::new(PADDING, PADDING, PADDING);
+        Self::new(self.min - padding, self.max + padding)
+    }
+    /// The proportion of a line segment at which it enters the box, if it
+    /// does at all.
+    fn segment_entry(&self, p0: Vec3, p1: Vec3) -> Option<Float> {
+        let (mut enter, mut exit): (Float, Float) = (0.0, 1.0);
+        for (start, delta, min, max) in [
+            (p0.x, p1.x - p0.x, self.min.x, self.max.x),
+            (p0.y, p1.y - p0.y, self.min.y, self.max.y),
+            (p0.z, p1.z - p0.z, self.min.z, self.max.z),
+        ] {
+            if delta == 0.0 {
+                if start < min || start > max {
+                    return None;
+                }
+                continue;
+            }
+            let (t0, t1) = ((min - start) / delta, (max - start) / delta);
+            enter = enter.max(t0.min(t1));
+            exit = exit.min(t0.max(t1));
+        }
+        (enter <= exit).then_some(enter)
+    }
+}
+
+/// A triangle hit by a line segment.
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    /// Index of the mesh the triangle belongs to.
+    pub mesh: usize,
+    /// Index of the triangle within its mesh.
+    pub triangle: usize,
+    /// The proportion of the line segment before the triangle.
+    pub proportion: Float,
+}
+
+enum Node {
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        end: usize,
+    },
+    Branch {
+        bounds: Aabb,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Branch { bounds, .. } => bounds,
+        }
+    }
+}
+
+struct Entry {
+    mesh: usize,
+    triangle: usize,
+    bounds: Aabb,
+    shape: Triangle,
+}
+
+/// A bounding volume hierarchy over the triangles of several meshes.
+///
+/// Triangles are copied in, so the hierarchy has to be rebuilt when the
+/// meshes change.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    entries: Vec<Entry>,
+}
+
+impl Bvh {
+    pub fn new(meshes: &[Mesh]) -> Self {
+        let mut entries: Vec<_> = meshes
+            .iter()
+            .enumerate()
+            .flat_map(|(mesh, m)| {
+                m.triangles
+                    .iter()
+                    .enumerate()
+                    .map(move |(triangle, t)| Entry {
+                        mesh,
+                        triangle,
+                        bounds: t.bounds().padded(),
+                        shape: *t,
+                    })
+            })
+            .collect();
+        let mut nodes = vec![];
+        if !entries.is_empty() {
+            let len = entries.len();
+            Self::build(&mut nodes, &mut entries, 0, len);
+        }
+        Self { nodes, entries }
+    }
+
+    /// Split the entries between `start` and `end` along the longest axis of
+    /// their centers, returning the index of the new node.
+    fn build(nodes: &mut Vec<Node>, entries: &mut [Entry], start: usize, end: usize) -> usize {
+        let slice = &mut entries[start..end];
+        let bounds = slice.iter().map(|e| e.bounds).reduce(Aabb::union).unwrap();
+        let index = nodes.len();
+        if slice.len() <= LEAF_SIZE {
+            nodes.push(Node::Leaf { bounds, start, end });
+            return index;
+        }
+        let centers = Aabb::from_points(slice.iter().map(|e| e.bounds.center())).unwrap();
+        let extent = centers.max - centers.min;
+        let axis = |v: Vec3| {
+            if extent.x >= extent.y && extent.x >= extent.z {
+                v.x
+            } else if extent.y >= extent.z {
+                v.y
+            } else {
+                v.z
+            }
+        };
+        let middle = slice.len() / 2;
+        slice.select_nth_unstable_by(middle, |a, b| {
+            axis(a.bounds.center()).total_cmp(&axis(b.bounds.center()))
+        });
+        // Reserve this node's place before its children are pushed.
+        nodes.push(Node::Leaf { bounds, start, end });
+        let left = Self::build(nodes, entries, start, start + middle);
+        let right = Self::build(nodes, entries, start + middle, end);
+        nodes[index] = Node::Branch {
+            bounds,
+            left,
+            right,
+        };
+        index
+    }
+
+    pub fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| *node.bounds())
+    }
+
+    /// Visit every leaf whose box passes `filter`.
+    fn visit(&self, mut filter: impl FnMut(&Aabb) -> bool, mut leaf: impl FnMut(&[Entry])) {
+        let mut stack = vec![];
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !filter(node.bounds()) {
+                continue;
+            }
+            match *node {
+                Node::Leaf { start, end, .. } => leaf(&self.entries[start..end]),
+                Node::Branch { left, right, .. } => stack.extend([right, left]),
+            }
+        }
+    }
+
+    /// The `(mesh, triangle)` indices of triangles whose boxes overlap `aabb`.
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<(usize, usize)> {
+        let mut found = vec![];
+        self.visit(
+            |bounds| bounds.intersects(aabb),
+            |entries| {
+                found.extend(
+                    entries
+                        .iter()
+                        .filter(|e| e.bounds.intersects(aabb))
+                        .map(|e| (e.mesh, e.triangle)),
+                )
+            },
+        );
+        found
+    }
+
+    /// The first triangle hit along a line segment.
+    pub fn nearest_hit(&self, p0: Vec3, p1: Vec3) -> Option<Hit> {
+        // Shared between the filter, which skips boxes past the nearest hit so
+        // far, and the leaves which update it.
+        let nearest: Cell<Option<Hit>> = Cell::new(None);
+        let is_nearer = |t: Float| nearest.get().is_none_or(|hit| t < hit.proportion);
+        self.visit(
+            |bounds| bounds.segment_entry(p0, p1).is_some_and(is_nearer),
+            |entries| {
+                for hit in entries.iter().filter_map(|e| e.hit(p0, p1)) {
+                    if is_nearer(hit.proportion) {
+                        nearest.set(Some(hit));
+                    }
+                }
+            },
+        );
+        nearest.get()
+    }
+
+    /// Every triangle hit along a line segment, in no particular order.
+    pub fn hits(&self, p0: Vec3, p1: Vec3) -> Vec<Hit> {
+        let mut hits = vec![];
+        self.visit(
+            |bounds| bounds.segment_entry(p0, p1).is_some(),
+            |entries| hits.extend(entries.iter().filter_map(|e| e.hit(p0, p1))),
+        );
+        hits
+    }
+}
+
+impl Entry {
+    fn hit(&self, p0: Vec3, p1: Vec3) -> Option<Hit> {
+        self.bounds.segment_entry(p0, p1)?;
+        Some(Hit {
+            mesh: self.mesh,
+            triangle: self.triangle,
+            proportion: self.shape.intersect_proportion(p0, p1)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod bvh_test {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::{Aabb, Bvh};
+    use crate::{approx_eq, math::Vec3, mesh::Mesh};
+
+    fn meshes() -> Vec<Mesh> {
+        vec![
+            Mesh::cuboid(Vec3::new(1.5, 1.5, 1.5), Vec3::new(3.5, 4.5, 5.5)),
+            Mesh::cuboid(Vec3::new(5.25, 0.25, 2.75), Vec3::new(7.75, 2.25, 6.75)),
+            Mesh::cuboid(Vec3::new(0.5, 6.5, 0.5), Vec3::new(7.5, 7.5, 1.5)),
+        ]
+    }
+
+    #[test]
+    fn nearest_hit_matches_linear_scan() {
+        let meshes = meshes();
+        let bvh = Bvh::new(&meshes);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut point = || {
+            Vec3::new(
+                rng.random_range(0.0..8.0),
+                rng.random_range(0.0..8.0),
+                rng.random_range(0.0..8.0),
+            )
+        };
+        for _ in 0..1000 {
+            let (p0, p1) = (point(), point());
+            let expected = meshes
+                .iter()
+                .flat_map(|m| m.triangles.iter())
+                .filter_map(|t| t.intersect_proportion(p0, p1))
+                .min_by(|a, b| a.total_cmp(b));
+            let found = bvh.nearest_hit(p0, p1).map(|hit| hit.proportion);
+            assert_eq!(found, expected, "segment from {p0} to {p1}");
+        }
+    }
+
+    #[test]
+    fn hits_through_cuboid() {
+        let bvh = Bvh::new(&meshes());
+        let mut hits = bvh.hits(Vec3::new(0.0, 2.0, 2.0), Vec3::new(8.0, 2.0, 2.0));
+        hits.sort_by(|a, b| a.proportion.total_cmp(&b.proportion));
+        assert_eq!(hits.len(), 2);
+        assert!(approx_eq(hits[0].proportion * 8.0, 1.5));
+        assert!(approx_eq(hits[1].proportion * 8.0, 3.5));
+        assert!(hits.iter().all(|hit| hit.mesh == 0));
+    }
+
+    #[test]
+    fn query_aabb_finds_overlapping() {
+        let bvh = Bvh::new(&meshes());
+        let found = bvh.query_aabb(&Aabb::new(
+            Vec3::new(6.0, 1.0, 3.0),
+            Vec3::new(6.5, 1.5, 3.5),
+        ));
+        assert!(found.is_empty());
+        let found = bvh.query_aabb(&Aabb::new(
+            Vec3::new(7.0, 1.0, 3.0),
+            Vec3::new(8.0, 1.5, 3.5),
+        ));
+        assert!(!found.is_empty());
+        assert!(found.iter().all(|(mesh, _)| *mesh == 1));
+    }
+}
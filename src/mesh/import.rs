@@ -0,0 +1,366 @@
+use std::{fmt::Display, path::Path};
+
+use crate::{
+    math::{Matrix3, Vec3},
+    mesh::{Mesh, Triangle},
+    Float,
+};
+
+/// How to place an imported mesh in lattice coordinates.
+///
+/// Points are scaled, then rotated about the origin, then translated.
+#[derive(Clone, Debug)]
+pub struct Placement {
+    pub scale: Float,
+    pub rotation: Matrix3,
+    pub translation: Vec3,
+}
+
+impl Default for Placement {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            rotation: Matrix3::IDENTITY,
+            translation: Vec3::ZERO,
+        }
+    }
+}
+
+impl Placement {
+    pub fn apply(&self, point: Vec3) -> Vec3 {
+        &self.rotation * (self.scale * point) + self.translation
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(std::io::Error),
+    /// The file extension is not one of `stl` or `obj`.
+    UnknownFormat(String),
+    /// A text file has a malformed line, numbered from 1.
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// A binary STL file is shorter than its triangle count says.
+    Truncated {
+        expected: usize,
+        found: usize,
+    },
+    /// The file parsed but has no triangles.
+    Empty,
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::Io(error) => write!(f, "could not read mesh: {error}"),
+            ImportError::UnknownFormat(extension) => {
+                write!(f, "unknown mesh format {extension:?}, expected stl or obj")
+            }
+            ImportError::Parse { line, message } => write!(f, "line {line}: {message}"),
+            ImportError::Truncated { expected, found } => write!(
+                f,
+                "binary stl should be {expected} bytes long but is {found} bytes"
+            ),
+            ImportError::Empty => write!(f, "mesh has no triangles"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<std::io::Error> for ImportError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+fn parse_error(line: usize, message: impl Into<String>) -> ImportError {
+    ImportError::Parse {
+        line,
+        message: message.into(),
+    }
+}
+
+/// Parse the next three words as a point.
+fn parse_point<'a>(
+    words: &mut impl Iterator<Item = &'a str>,
+    line: usize,
+) -> Result<Vec3, ImportError> {
+    let mut coord = || {
+        let word = words
+            .next()
+            .ok_or_else(|| parse_error(line, "expected 3 coordinates"))?;
+        word.parse::<Float>()
+            .map_err(|_| parse_error(line, format!("invalid coordinate {word:?}")))
+    };
+    Ok(Vec3::new(coord()?, coord()?, coord()?))
+}
+
+impl Mesh {
+    /// Load an STL or OBJ file, chosen by its extension.
+    pub fn load(path: impl AsRef<Path>, placement: &Placement) -> Result<Self, ImportError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match extension.as_str() {
+            "stl" => Self::from_stl(&std::fs::read(path)?, placement),
+            "obj" => Self::from_obj(&std::fs::read_to_string(path)?, placement),
+            _ => Err(ImportError::UnknownFormat(extension)),
+        }
+    }
+
+    /// Parse an ASCII or binary STL file.
+    pub fn from_stl(bytes: &[u8], placement: &Placement) -> Result<Self, ImportError> {
+        // Binary files may also start with "solid", so trust the size first.
+        let binary_size = bytes
+            .get(80..84)
+            .map(|count| 84 + 50 * u32::from_le_bytes(count.try_into().unwrap()) as usize);
+        let text = (binary_size != Some(bytes.len())
+            && bytes.trim_ascii_start().starts_with(b"solid"))
+        .then(|| std::str::from_utf8(bytes).ok())
+        .flatten();
+        let triangles = match text {
+            Some(text) => parse_ascii_stl(text)?,
+            None => parse_binary_stl(bytes)?,
+        };
+        Self::placed(triangles, placement)
+    }
+
+    /// Parse a Wavefront OBJ file, splitting polygons into triangle fans.
+    ///
+    /// Only vertices and faces are read, everything else is ignored.
+    pub fn from_obj(text: &str, placement: &Placement) -> Result<Self, ImportError> {
+        let mut vertices = vec![];
+        let mut triangles = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i + 1;
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("v") => vertices.push(parse_point(&mut words, line_number)?),
+                Some("f") => {
+                    let face = words
+                        .map(|word| {
+                            // Texture and normal indices after slashes are ignored.
+                            let index = word.split('/').next().unwrap_or_default();
+                            let index: isize = index.parse().map_err(|_| {
+                                parse_error(line_number, format!("invalid vertex index {word:?}"))
+                            })?;
+                            // Indices start at 1, negative ones count back from the last vertex.
+                            let resolved = match index {
+                                1.. => index - 1,
+                                ..0 => vertices.len() as isize + index,
+                                0 => -1,
+                            };
+                            usize::try_from(resolved)
+                                .ok()
+                                .and_then(|i| vertices.get(i).copied())
+                                .ok_or_else(|| {
+                                    parse_error(
+                                        line_number,
+                                        format!("vertex index {index} out of range"),
+                                    )
+                                })
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if face.len() < 3 {
+                        return Err(parse_error(line_number, "face has fewer than 3 vertices"));
+                    }
+                    triangles.extend(
+                        face.windows(2)
+                            .skip(1)
+                            .map(|edge| Triangle::new(face[0], edge[0], edge[1])),
+                    );
+                }
+                _ => {}
+            }
+        }
+        Self::placed(triangles, placement)
+    }
+
+    fn placed(triangles: Vec<Triangle>, placement: &Placement) -> Result<Self, ImportError> {
+        if triangles.is_empty() {
+            return Err(ImportError::Empty);
+        }
+        let mut mesh = Self::new(
+            triangles
+                .iter()
+                .map(|t| {
+                    let [p0, p1, p2] = t.points().map(|p| placement.apply(p));
+                    Triangle::new(p0, p1, p2)
+                })
+                .collect(),
+        );
+        mesh.center = mesh.bounds().unwrap().center();
+        Ok(mesh)
+    }
+}
+
+fn parse_ascii_stl(text: &str) -> Result<Vec<Triangle>, ImportError> {
+    let mut triangles = vec![];
+    let mut facet: Option<(usize, Vec<Vec3>)> = None;
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i + 1;
+        let mut words = line.split_whitespace();
+        match (words.next(), &mut facet) {
+            (Some("facet"), None) => facet = Some((line_number, vec![])),
+            (Some("facet"), Some(_)) => {
+                return Err(parse_error(line_number, "facet started before endfacet"))
+            }
+            (Some("vertex"), Some((_, points))) => {
+                points.push(parse_point(&mut words, line_number)?)
+            }
+            (Some("vertex"), None) => return Err(parse_error(line_number, "vertex outside facet")),
+            (Some("endfacet"), Some((start, points))) => {
+                let [p0, p1, p2] = points[..] else {
+                    return Err(parse_error(
+                        *start,
+                        format!("facet has {} vertices instead of 3", points.len()),
+                    ));
+                };
+                triangles.push(Triangle::new(p0, p1, p2));
+                facet = None;
+            }
+            (Some("endfacet"), None) => {
+                return Err(parse_error(line_number, "endfacet without facet"))
+            }
+            _ => {}
+        }
+    }
+    if let Some((start, _)) = facet {
+        return Err(parse_error(start, "facet is never closed"));
+    }
+    Ok(triangles)
+}
+
+fn parse_binary_stl(bytes: &[u8]) -> Result<Vec<Triangle>, ImportError> {
+    let Some(count) = bytes.get(80..84) else {
+        return Err(ImportError::Truncated {
+            expected: 84,
+            found: bytes.len(),
+        });
+    };
+    let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+    let expected = 84 + 50 * count;
+    if bytes.len() < expected {
+        return Err(ImportError::Truncated {
+            expected,
+            found: bytes.len(),
+        });
+    }
+    let float = |b: &[u8]| f32::from_le_bytes(b.try_into().unwrap()) as Float;
+    let point = |b: &[u8]| Vec3::new(float(&b[0..4]), float(&b[4..8]), float(&b[8..12]));
+    // Each record is a normal, three vertices and two attribute bytes.
+    Ok(bytes[84..expected]
+        .chunks_exact(50)
+        .map(|record| {
+            Triangle::new(
+                point(&record[12..24]),
+                point(&record[24..36]),
+                point(&record[36..48]),
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod import_test {
+    use super::{ImportError, Placement};
+    use crate::{
+        math::{consts, Matrix3, Vec3},
+        mesh::Mesh,
+    };
+
+    const ASCII_STL: &str = "solid square
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid square
+";
+
+    #[test]
+    fn ascii_stl() {
+        let mesh = Mesh::from_stl(ASCII_STL.as_bytes(), &Placement::default()).unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+        assert!(mesh.triangles[1].points()[2].approx_eq(Vec3::new(0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn binary_stl() {
+        // Binary files may start with "solid" too.
+        let mut bytes = b"solid but actually binary".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend(1u32.to_le_bytes());
+        for v in [
+            0.0f32, 0.0, 1.0, 0.0, 0.0, 0.0, 2.0, 0.0, 0.0, 0.0, 3.0, 0.0,
+        ] {
+            bytes.extend(v.to_le_bytes());
+        }
+        bytes.extend([0, 0]);
+        let mesh = Mesh::from_stl(&bytes, &Placement::default()).unwrap();
+        assert_eq!(mesh.triangles.len(), 1);
+        assert!(mesh.triangles[0].points()[2].approx_eq(Vec3::new(0.0, 3.0, 0.0)));
+
+        bytes.pop();
+        assert!(matches!(
+            Mesh::from_stl(&bytes, &Placement::default()),
+            Err(ImportError::Truncated {
+                expected: 134,
+                found: 133
+            })
+        ));
+    }
+
+    #[test]
+    fn obj_with_placement() {
+        let obj =
+            "# a quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1 -1//1\n";
+        let placement = Placement {
+            scale: 2.0,
+            rotation: Matrix3::rotation(Vec3::new(0.0, 0.0, 1.0), consts::PI),
+            translation: Vec3::new(5.0, 5.0, 5.0),
+        };
+        let mesh = Mesh::from_obj(obj, &placement).unwrap();
+        assert_eq!(mesh.triangles.len(), 2);
+        assert!(mesh.triangles[0].points()[1].approx_eq(Vec3::new(3.0, 5.0, 5.0)));
+        assert!(mesh.center.approx_eq(Vec3::new(4.0, 4.0, 5.0)));
+    }
+
+    #[test]
+    fn malformed_files() {
+        let placement = Placement::default();
+        let error = Mesh::from_obj("v 0 0 0\nv 1 0 x\n", &placement).unwrap_err();
+        assert!(
+            matches!(error, ImportError::Parse { line: 2, .. }),
+            "{error}"
+        );
+        let error = Mesh::from_obj("v 0 0 0\nf 1 2 3\n", &placement).unwrap_err();
+        assert!(
+            matches!(error, ImportError::Parse { line: 2, .. }),
+            "{error}"
+        );
+        let error = Mesh::from_obj("v 0 0 0\n", &placement).unwrap_err();
+        assert!(matches!(error, ImportError::Empty), "{error}");
+        let stl = ASCII_STL.replacen("      vertex 1 1 0\n", "", 1);
+        let error = Mesh::from_stl(stl.as_bytes(), &placement).unwrap_err();
+        assert!(
+            matches!(error, ImportError::Parse { line: 2, .. }),
+            "{error}"
+        );
+    }
+}